@@ -2,9 +2,11 @@ use serde::Serialize;
 use crate::miner::Handle as MinerHandle;
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
+use crate::blockchain::Blockchain;
 
 use log::info;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use tiny_http::Header;
 use tiny_http::Response;
@@ -15,6 +17,7 @@ pub struct Server {
     handle: HTTPServer,
     miner: MinerHandle,
     network: NetworkServerHandle,
+    blockchain: Arc<Mutex<Blockchain>>,
 }
 
 #[derive(Serialize)]
@@ -23,6 +26,23 @@ struct ApiResponse {
     message: String,
 }
 
+/// Coins in circulation compared with what the subsidy schedule allows
+#[derive(Serialize)]
+struct SupplyResponse {
+    height: u32,
+    circulating: u64,   // UTXO集合中所有output的总值
+    issued: u64,        // 到当前高度为止按奖励规则发行的总量
+    max_supply: Option<u64>,
+    next_subsidy: u32,
+}
+
+/// A transaction this node refused, with the reason
+#[derive(Serialize)]
+struct RejectResponse {
+    hash: String,
+    error: String,
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -36,22 +56,34 @@ macro_rules! respond_result {
     }};
 }
 
+macro_rules! respond_json {
+    ( $req:expr, $message:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let resp = Response::from_string(serde_json::to_string_pretty(&$message).unwrap())
+            .with_header(content_type);
+        $req.respond(resp).unwrap();
+    }};
+}
+
 impl Server {
     pub fn start(
         addr: std::net::SocketAddr,
         miner: &MinerHandle,
         network: &NetworkServerHandle,
+        blockchain: &Arc<Mutex<Blockchain>>,
     ) {
         let handle = HTTPServer::http(&addr).unwrap();
         let server = Self {
             handle,
             miner: miner.clone(),
             network: network.clone(),
+            blockchain: Arc::clone(blockchain),
         };
         thread::spawn(move || {
             for req in server.handle.incoming_requests() {
                 let miner = server.miner.clone();
                 let network = server.network.clone();
+                let blockchain = Arc::clone(&server.blockchain);
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -87,6 +119,60 @@ impl Server {
                             miner.start(lambda);
                             respond_result!(req, true, "ok");
                         }
+                        "/blockchain/longest-chain" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let blockchain = blockchain.lock().unwrap();
+                            let from = params.get("from").and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+                            let to = params.get("to").and_then(|v| v.parse::<u32>().ok()).unwrap_or(u32::MAX);
+                            let hashes: Vec<String> = blockchain
+                                .blocks_in_range(from..to)
+                                .map(|h| h.to_string())
+                                .collect();
+                            respond_json!(req, hashes);
+                        }
+                        "/blockchain/block-at-height" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let height = match params.get("height").map(|v| v.parse::<u32>()) {
+                                Some(Ok(v)) => v,
+                                Some(Err(e)) => {
+                                    respond_result!(req, false, format!("error parsing height: {}", e));
+                                    return;
+                                }
+                                None => {
+                                    respond_result!(req, false, "missing height");
+                                    return;
+                                }
+                            };
+                            let blockchain = blockchain.lock().unwrap();
+                            match blockchain.block_at_height(height) {
+                                Some(hash) => respond_result!(req, true, hash),
+                                None => respond_result!(req, false, "no block at this height"),
+                            }
+                        }
+                        "/blockchain/supply" => {
+                            let blockchain = blockchain.lock().unwrap();
+                            let height = blockchain.longest_height;
+                            let supply = SupplyResponse {
+                                height: height,
+                                circulating: blockchain.ledger_state.total_value(),
+                                issued: blockchain.params.issued_at_height(height),
+                                max_supply: blockchain.params.max_supply(),
+                                next_subsidy: blockchain.params.block_subsidy(height + 1),
+                            };
+                            respond_json!(req, supply);
+                        }
+                        "/transaction/rejects" => {
+                            let blockchain = blockchain.lock().unwrap();
+                            let rejects: Vec<RejectResponse> = blockchain
+                                .recent_rejects
+                                .iter()
+                                .map(|(hash, e)| RejectResponse {
+                                    hash: hash.to_string(),
+                                    error: format!("{:?}", e),
+                                })
+                                .collect();
+                            respond_json!(req, rejects);
+                        }
                         "/network/ping" => {
                             network.broadcast(Message::Ping(String::from("Test ping")));
                             respond_result!(req, true, "ok");
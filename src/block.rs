@@ -5,6 +5,7 @@ use chrono::prelude::*;
 
 use crate::crypto::hash::{H256, Hashable};
 use crate::crypto::merkle::*;
+use crate::encoding::Encodable;
 use super::transaction::*;
 
 #[derive(Hash, Eq, PartialEq,Debug, Default, Clone, Serialize, Deserialize)]
@@ -35,29 +36,36 @@ pub struct Block {
 
 impl Hashable for Header {
     fn hash(&self) -> H256 {
-        let strtype = serde_json::to_string(&self).unwrap();
-        let bytetype = strtype.into_bytes();
-        ring::digest::digest(&ring::digest::SHA256, &bytetype).into()
-        //unimplemented!()
+        ring::digest::digest(&ring::digest::SHA256, &self.canonical_bytes()).into()
     }
 }
 
 impl Hashable for Transaction {
     fn hash(&self) -> H256 {
-        let strtype = serde_json::to_string(&self).unwrap();
-        let bytetype = strtype.into_bytes();
-        ring::digest::digest(&ring::digest::SHA256, &bytetype).into()
-
-        //unimplemented!()
+        ring::digest::digest(&ring::digest::SHA256, &self.canonical_bytes()).into()
     }
 }
 
 impl Hashable for SignedTransaction {
     fn hash(&self) -> H256 {
-        let strtype = serde_json::to_string(&self).unwrap();
-        let bytetype = strtype.into_bytes();
-        ring::digest::digest(&ring::digest::SHA256, &bytetype).into()
-        //unimplemented!()
+        ring::digest::digest(&ring::digest::SHA256, &self.canonical_bytes()).into()
+    }
+}
+
+impl Content {
+    /// Hash of the merkle root over the txids and the merkle root over the wtxids, both in block
+    /// order, so that the header commits to the signatures as well
+    pub fn merkle_root(&self) -> H256 {
+        if self.transactions.is_empty() {
+            return H256::default();
+        }
+        let wtxids: Vec<H256> = self.transactions.iter()
+            .map(|id| self.transaction_detail.get(id).map(|tx| tx.wtxid()).unwrap_or_default())
+            .collect();
+        let mut roots = Vec::new();
+        roots.extend_from_slice(MerkleTree::new(&self.transactions).root().as_ref());
+        roots.extend_from_slice(MerkleTree::new(&wtxids).root().as_ref());
+        ring::digest::digest(&ring::digest::SHA256, &roots).into()
     }
 }
 
@@ -78,6 +86,7 @@ pub mod test {
         let mut rng = rand::thread_rng();
         let new_nonce: u32 = rng.gen();     // nonce generated
         let diffc: [u8; 32] = [0;32];
+        let easiest: [u8; 32] = [255;32];   // 任意hash都满足
         let mut input_data: Vec<H256> = Vec::new();
         input_data.push(From::from(diffc));
 
@@ -90,7 +99,7 @@ pub mod test {
         let new_header = Header{
             parent_pointer : *parent,
             nonce : new_nonce,
-            difficulty : From::from(diffc),
+            difficulty : From::from(easiest),
             timestamp : Local::now().timestamp_millis(),
             merkle_root : new_tree.root(),
         };
@@ -1,34 +1,104 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::block::Block;
 use crate::crypto::merkle::*;
 //use super::transaction::Transaction;
 use super::block::*;
 use crate::crypto::hash::{H256, Hashable};
+use crate::crypto::u256::U256;
 use crate::transaction::*;
 use ring::signature::{self, Ed25519KeyPair, Signature, KeyPair};
 use rand::Rng;
+use chrono::prelude::*;
+use log::{error, info, warn};
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use crate::store::BlockStore;
+use crate::params::ChainParams;
+use crate::utxo::{OutPoint, UtxoEntry, UtxoSet};
+use crate::mempool::{Mempool, MempoolEntry, MempoolError};
+
+pub const MAX_RECENT_REJECTS: usize = 100;
 
 #[derive( Eq, PartialEq, Debug, Default, Clone)] // Hash,
 pub struct Blockchain {
     pub chain :  HashMap<H256, Block>,
     pub tip : H256,
     pub longest_height : u32,
-    pub longest_chain : Vec<H256>,
+    pub longest_chain : Vec<H256>,     // 最长链的高度索引：longest_chain[h] 为高度h的块
+
+    pub mempool: Mempool,
+    pub recent_rejects: VecDeque<(H256, MempoolError)>,   // 最近拒绝的tx及原因，最多MAX_RECENT_REJECTS条
+    pub ledger_state: UtxoSet,
+    pub undo: HashMap<H256, HashMap<OutPoint, UtxoEntry>>,  // 每个已连接块花掉的output
+    pub chain_work: HashMap<H256, U256>,    // 每个块到genesis的累计work
+    pub confirmed_txs: HashSet<H256>,       // 最长链上各块的txid
+
+    pub params: ChainParams,
+
+    pub store: Option<BlockStore>,  // 设置了datadir时，块与快照写入磁盘
+}
 
-    pub tx_mempool: HashMap<H256, SignedTransaction>,
-    pub ledger_state: HashMap<H256, TxOutput>,
+/// Ledger state saved alongside the block store, valid at `tip`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ChainSnapshot {
+    tip: H256,
+    ledger_state: UtxoSet,
+    undo: HashMap<H256, HashMap<OutPoint, UtxoEntry>>,
+    mempool: Vec<MempoolEntry>,
+}
+
+/// Expected number of hashes needed to mine a block at this difficulty, i.e. 2^256 / (target + 1)
+pub fn block_work(difficulty: &H256) -> U256 {
+    let target = U256::from(difficulty);
+    match target.overflowing_add(&U256::from(1)) {
+        // 2^256 / (target + 1) = (2^256 - target - 1) / (target + 1) + 1
+        (divisor, false) => (!target).div_rem(&divisor).0.saturating_add(&U256::from(1)),
+        (_, true) => U256::from(1),
+    }
+}
+
+/// Reasons a block is rejected by `Blockchain::validate_block`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum BlockValidationError {
+    UnknownParent(H256),
+    TooLarge(usize),
+    BadDifficulty { expected: H256, found: H256 },
+    HashAboveTarget,
+    TimestampTooOld { median_time_past: i64 },
+    TimestampTooNew { max_allowed: i64 },
+    TransactionMismatch(H256),  // id与transaction_detail对不上
+    DuplicateTransaction(H256),
+    BadMerkleRoot,
+    NoCoinbase,
+    TooManyCoinbase,
+    CoinbaseTooLarge { claimed: u64, allowed: u64 },   // 超过奖励加手续费
+    BadCoinbaseHeight { expected: u32, found: u32 },
+    ReorgTooDeep { fork_height: u32 },     // 分叉点以上的块超过max_reorg_depth，undo已删除
+    InvalidTransaction(H256, TxValidationError),
+}
+
+/// Blocks that left and joined the longest chain during one insert
+#[derive(Eq, PartialEq, Debug, Default, Clone)]
+pub struct Reorg {
+    pub disconnected: Vec<H256>,    // 从旧tip往下，按断开顺序
+    pub connected: Vec<H256>,       // 从分叉点往上，按连接顺序
 }
 
 
 
 
 impl Blockchain {
-    /// Create a new blockchain, only containing the genesis block
+    /// Create a new blockchain, only containing the genesis block of the main network
     /// 函数-生成新链
     pub fn new() -> Self {
-        let new_nonce: u32 = 2083236893;
-        let diffc: [u8; 32] = [0,5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];
+        Blockchain::with_params(ChainParams::main())
+    }
+
+    /// Create a new blockchain of the network described by `params`, only containing its genesis block
+    pub fn with_params(params: ChainParams) -> Self {
+        let new_nonce: u32 = params.genesis_nonce;
+        let diffc: [u8; 32] = (&params.genesis_difficulty).into();
         let zero :  [u8; 32] = [0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];
         // 初始化merkle tree，为了防止panic
         let mut input_data: Vec<H256> = Vec::new();
@@ -45,7 +115,7 @@ impl Blockchain {
             parent_pointer : From::from(zero),
             nonce : new_nonce,
             difficulty : From::from(diffc),
-            timestamp : 1231006505,
+            timestamp : params.genesis_timestamp,
             merkle_root : new_tree.root(),
         };
         let headerhash = new_header.clone().hash();
@@ -56,11 +126,13 @@ impl Blockchain {
 
         let mut veclongest: Vec<H256> = Vec::new();
         veclongest.push(headerhash);
+        let mut work = HashMap::new();
+        work.insert(headerhash, block_work(&new_block.header.difficulty));
         let mut map = HashMap::new();
         map.insert(headerhash, new_block);
 
         // 初始化给4个可花state
-        let mut state_init = HashMap::new();
+        let mut state_init = UtxoSet::new();
         //let mut rng = rand::thread_rng();
         //for i in 0..4 {
           //  let rand_txhash:[u8;32] = rng.gen();
@@ -74,83 +146,591 @@ impl Blockchain {
             longest_height : 0,
             longest_chain : veclongest,
 
-            tx_mempool : HashMap::new(),
+            mempool: Mempool::new(params.max_mempool_bytes),
+            recent_rejects: VecDeque::new(),
             ledger_state: state_init, 
+            undo: HashMap::new(),
+            chain_work: work,
+            confirmed_txs: HashSet::new(),
+
+            params: params,
+
+            store: None,
         }
         //unimplemented!()
     }
 
-    /// Insert a block into blockchain
-    pub fn insert(&mut self, block: &Block) {
-        let mut blockcln = block.clone();
-        // 确定parent
-        let previousblock = block.header.parent_pointer;    // 确定插入块的parent 哈希值
-        // 初始化parent block为插入的block
-        // 遍历chain中的block，找前一个块，存在parent_block中
-        
-        //找到父块，确定当前块高度，以及当前块是否是最长链第一个到达的块
-        let parentblock = self.chain.get(&previousblock).unwrap();   // 找到父块
-        let tipblock = self.chain.get(&self.tip).unwrap();   // 找到最高块
-
-        let txs = block.content.transactions.clone();   //块中包含的tx
-
-        blockcln.content.height = parentblock.content.height + 1;       // 确认插入块高度
-        if (parentblock.content.height + 1) == self.longest_height{    // 如果新块的父块高度是当前最高高度 - 1
-            if tipblock.header.timestamp > blockcln.header.timestamp {      // 当前块比最高块挖的早
-                // 原先height
-                let old_tip_block = self.chain.get(&self.tip()).unwrap().clone();
-                let old_tx = old_tip_block.content.transactions.clone();
-                // 改tip
-                self.tip = blockcln.header.hash();
-                self.longest_chain.pop();
-                self.longest_chain.push(self.tip);
-                self.longest_height = blockcln.content.height;
-                // 改tx相关
-                // 弹出先前confirmed的state，将交易恢复到mempool中
-                for i in old_tx {
-                    let transac_now = old_tip_block.content.transaction_detail.get(&i).unwrap().clone();
-                    if transac_now.tx.input.prev_tx != [0;32].into() {
-                        self.ledger_state.remove(&i);   //弹出state
-                        self.tx_mempool.insert(i, transac_now); // 恢复mempool
-                    }
-                }
-                // 加入新state
-                for j in txs {
-                    self.ledger_state.insert(j, block.content.transaction_detail.get(&j).unwrap().clone().tx.output);
+    /// Insert a block into blockchain, switching to its branch if that branch has the most work.
+    /// Transactions of a block are checked when it is connected; if a block on the new branch
+    /// turns out invalid, the old chain is restored and the invalid blocks are dropped.
+    pub fn insert(&mut self, block: &Block) -> Result<Reorg, BlockValidationError> {
+        let mut reorg = Reorg::default();
+        let hash = block.hash();
+        if self.chain.contains_key(&hash) {
+            return Ok(reorg);
+        }
+        // work更多才切换；work相同时保留先收到的分支
+        let work = self.attach(block)?;
+        let switch = work > *self.chain_work.get(&self.tip).unwrap();
+
+        if switch {
+            // 沿新块往回走，直到碰到最长链上的块（分叉点）
+            let mut branch: Vec<H256> = Vec::new();
+            let mut cursor = hash;
+            while !self.is_in_main_chain(&cursor) {
+                branch.push(cursor);
+                cursor = self.chain.get(&cursor).unwrap().header.parent_pointer;
+            }
+            branch.reverse();
+            let fork_height = self.chain.get(&cursor).unwrap().content.height;
+            // 分叉点之上的块都要有undo才能回滚账本
+            let undo_missing = self.longest_chain[fork_height as usize + 1..].iter().any(|old| !self.undo.contains_key(old));
+            if self.longest_height - fork_height > self.params.max_reorg_depth || undo_missing {
+                return Err(BlockValidationError::ReorgTooDeep { fork_height: fork_height });
+            }
+
+            // 切换失败时原样恢复mempool：连上的块会移走已确认及冲突的tx，断开的块会挤掉冲突的tx。
+            // 只接一个块时connect_block在改动mempool之前就会失败，不用备份
+            let pool_before = if branch.len() > 1 || self.longest_height > fork_height {
+                Some(self.mempool.clone())
+            } else {
+                None
+            };
+            // 断开分叉点之上的旧块
+            while self.longest_height > fork_height {
+                match self.disconnect_block(true) {
+                    Some(old) => reorg.disconnected.push(old),
+                    None => break,
                 }
             }
-        } else if blockcln.content.height > self.longest_height {
-            self.tip = blockcln.header.hash();
-            self.longest_chain.push(self.tip);
-            self.longest_height = blockcln.content.height;
-            // 加入新state
-            for j in txs {
-                let info = block.content.transaction_detail.get(&j).unwrap().clone();
-                if info.tx.input.prev_tx != [0;32].into() {
-                    self.ledger_state.insert(j, block.content.transaction_detail.get(&j).unwrap().clone().tx.output);
+            // 按顺序连接新分支
+            for (n, new) in branch.iter().enumerate() {
+                if let Err(e) = self.connect_block(new) {
+                    // 新分支无效：退回原来的链和mempool，丢弃无效块及其后代
+                    while self.longest_height > fork_height {
+                        self.disconnect_block(false).expect("just connected block must have undo");
+                    }
+                    for old in reorg.disconnected.iter().rev() {
+                        self.connect_block(old).expect("previously connected block must reconnect");
+                    }
+                    if let Some(pool) = pool_before {
+                        self.mempool = pool;
+                    }
+                    self.discard_blocks(&branch[n..]);
+                    return Err(e);
                 }
-                
+                reorg.connected.push(*new);
             }
-        } 
+            // 切换成功后再按新tip裁剪undo，失败的切换不会丢掉旧链的undo
+            self.prune_undo();
+            // tip变了，池中的tx重新对照新tip检查
+            self.revalidate_mempool();
+        }
 
-        // 插入块
-        self.chain.insert(blockcln.header.hash(), blockcln);
-        println!("Longest chain: {:?}", self.longest_chain);
-        let mut memp = Vec::new();
-        let mut state = Vec::new();
+        self.persist(&hash);
 
-        for i in self.tx_mempool.keys() {
-            memp.push(i);
+        println!("Longest chain: {:?}", self.longest_chain);
+        if !reorg.disconnected.is_empty() {
+            info!("Reorg: disconnected {:?}, connected {:?}", reorg.disconnected, reorg.connected);
         }
+        let memp = self.mempool.txids();
+        let mut state = Vec::new();
 
-        for j in self.ledger_state.keys() {
+        for j in self.ledger_state.outpoints() {
             state.push(j);
         }
 
 
         println!("tx mempool: {:?}",memp);
         println!("state: {:?}",state);
-        // unimplemented!()
+        Ok(reorg)
+    }
+
+    /// Drop the given blocks and every stored block descending from them, so that no block is
+    /// left pointing at a parent that is gone
+    fn discard_blocks(&mut self, bad: &[H256]) {
+        let mut doomed: HashSet<H256> = bad.iter().cloned().collect();
+        // 按高度从低到高，父块先于子块被标记
+        let mut stored: Vec<(u32, H256, H256)> = self.chain.iter()
+            .map(|(hash, block)| (block.content.height, *hash, block.header.parent_pointer))
+            .collect();
+        stored.sort();
+        for (_, hash, parent) in stored {
+            if doomed.contains(&parent) {
+                doomed.insert(hash);
+            }
+        }
+        for hash in doomed.iter() {
+            self.chain.remove(hash);
+            self.chain_work.remove(hash);
+        }
+    }
+
+    /// Store a block with its height and cumulative work, without connecting it
+    fn attach(&mut self, block: &Block) -> Result<U256, BlockValidationError> {
+        // 找到父块，确定当前块高度与累计work
+        let (parentheight, parentwork) = match self.chain.get(&block.header.parent_pointer) {
+            Some(parentblock) => (parentblock.content.height, *self.chain_work.get(&block.header.parent_pointer).unwrap()),
+            None => return Err(BlockValidationError::UnknownParent(block.header.parent_pointer)),   // 由调用方放入orphan
+        };
+        let mut blockcln = block.clone();
+        blockcln.content.height = parentheight + 1;       // 确认插入块高度
+        let work = parentwork.saturating_add(&block_work(&block.header.difficulty));
+
+        // 插入块
+        let hash = block.hash();
+        self.chain.insert(hash, blockcln);
+        self.chain_work.insert(hash, work);
+        Ok(work)
+    }
+
+    /// Write a newly inserted block, the ledger snapshot and the tip to the block store
+    fn persist(&mut self, hash: &H256) {
+        let store = match self.store.as_mut() {
+            Some(store) => store,
+            None => return,
+        };
+        let block = match self.chain.get(hash) {
+            Some(block) => block,
+            None => return,     // 无效块已被删除
+        };
+        let snapshot = ChainSnapshot {
+            tip: self.tip,
+            ledger_state: self.ledger_state.clone(),
+            undo: self.undo.clone(),
+            mempool: self.mempool.entries().cloned().collect(),
+        };
+        let written = store.put(block)
+            .and_then(|_| store.save_snapshot(&snapshot))
+            .and_then(|_| store.set_tip(&snapshot.tip));
+        if let Err(e) = written {
+            error!("Error writing block {:?} to the block store: {}", hash, e);
+        }
+    }
+
+    /// Load the blocks kept in `datadir` and keep writing new blocks there.
+    /// The saved ledger snapshot is used when it matches the stored tip; otherwise the stored
+    /// blocks are replayed from genesis.
+    pub fn open_store(&mut self, datadir: &Path) -> std::io::Result<()> {
+        let store = BlockStore::open(datadir)?;
+        let blocks = store.blocks()?;
+        let stored_tip = store.tip()?;
+        let snapshot: Option<ChainSnapshot> = store.load_snapshot()?;
+
+        let restored = match snapshot {
+            Some(snapshot) if Some(snapshot.tip) == stored_tip
+                && (snapshot.tip == self.tip || blocks.iter().any(|b| b.hash() == snapshot.tip)) => {
+                self.restore_snapshot(snapshot, &blocks)
+            }
+            _ => false,
+        };
+        if !restored {
+            // 没有可用快照，重放所有块
+            for block in blocks.iter() {
+                if let Err(e) = self.insert(block) {
+                    warn!("Skipping stored block {:?}: {:?}", block.hash(), e);
+                }
+            }
+        }
+        self.store = Some(store);
+        Ok(())
+    }
+
+    /// Attach the stored blocks and take the ledger from `snapshot`. Returns false, leaving the
+    /// chain as it was, if the snapshot tip does not lead back to genesis through stored blocks.
+    fn restore_snapshot(&mut self, snapshot: ChainSnapshot, blocks: &[Block]) -> bool {
+        let genesis = self.longest_chain[0];
+        let mut attached = self.clone();
+        for block in blocks.iter() {
+            if !attached.chain.contains_key(&block.hash()) {
+                if let Err(e) = attached.attach(block) {
+                    warn!("Skipping stored block {:?}: {:?}", block.hash(), e);
+                }
+            }
+        }
+        // 从快照tip往回重建最长链
+        let mut longest = Vec::new();
+        let mut cursor = snapshot.tip;
+        while let Some(block) = attached.chain.get(&cursor) {
+            longest.push(cursor);
+            if block.content.height == 0 {
+                break;
+            }
+            cursor = block.header.parent_pointer;
+        }
+        longest.reverse();
+        if longest.first() != Some(&genesis) {
+            warn!("Snapshot tip {:?} does not connect to genesis, replaying stored blocks", snapshot.tip);
+            return false;
+        }
+        *self = attached;
+        self.longest_height = (longest.len() - 1) as u32;
+        self.longest_chain = longest;
+        self.tip = snapshot.tip;
+        self.ledger_state = snapshot.ledger_state;
+        self.undo = snapshot.undo;
+        self.confirmed_txs = self.longest_chain.iter()
+            .flat_map(|hash| self.chain.get(hash).unwrap().content.transactions.iter().cloned())
+            .collect();
+        // 按到达时间放回，父tx在子tx之前
+        let mut pooled = snapshot.mempool;
+        pooled.sort_by_key(|entry| entry.time);
+        for entry in pooled {
+            let _ = self.mempool.insert(entry);
+        }
+        true
+    }
+
+    /// Check a block received from a peer before it is inserted.
+    /// Header, size, merkle root and coinbase rules are always checked. Transactions are checked
+    /// against the ledger state here when the block extends the tip; blocks on side branches have
+    /// their transactions checked when a reorg connects them.
+    pub fn validate_block(&self, block: &Block) -> Result<(), BlockValidationError> {
+        let parent = block.header.parent_pointer;
+        if !self.chain.contains_key(&parent) {
+            return Err(BlockValidationError::UnknownParent(parent));
+        }
+        let size = bincode::serialize(block).unwrap().len();
+        if size > self.params.max_block_size {
+            return Err(BlockValidationError::TooLarge(size));
+        }
+
+        // difficulty与工作量
+        let expected = self.next_difficulty(&parent);
+        if block.header.difficulty != expected {
+            return Err(BlockValidationError::BadDifficulty { expected: expected, found: block.header.difficulty });
+        }
+        if block.hash() > block.header.difficulty {
+            return Err(BlockValidationError::HashAboveTarget);
+        }
+
+        // 时间戳：晚于最近块的中位数，且不超前本地时间太多
+        let median_time_past = self.median_time_past(&parent);
+        if block.header.timestamp <= median_time_past {
+            return Err(BlockValidationError::TimestampTooOld { median_time_past: median_time_past });
+        }
+        let max_allowed = Local::now().timestamp_millis() + self.params.max_future_block_time;
+        if block.header.timestamp > max_allowed {
+            return Err(BlockValidationError::TimestampTooNew { max_allowed: max_allowed });
+        }
+
+        // 每个id都要对应transaction_detail中的tx
+        if block.content.transactions.len() != block.content.transaction_detail.len() {
+            for (id, _) in block.content.transaction_detail.iter() {
+                if !block.content.transactions.contains(id) {
+                    return Err(BlockValidationError::TransactionMismatch(*id));
+                }
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        for id in block.content.transactions.iter() {
+            if !seen.insert(*id) {
+                return Err(BlockValidationError::DuplicateTransaction(*id));
+            }
+            match block.content.transaction_detail.get(id) {
+                Some(tx) if tx.txid() == *id => {}
+                _ => return Err(BlockValidationError::TransactionMismatch(*id)),
+            }
+        }
+        if block.content.merkle_root() != block.header.merkle_root {
+            return Err(BlockValidationError::BadMerkleRoot);
+        }
+
+        // 矿工奖励：有且只有一笔，放在第一位
+        match block.content.transactions.first() {
+            Some(first) if is_coinbase(block.content.transaction_detail.get(first).unwrap()) => {}
+            _ => return Err(BlockValidationError::NoCoinbase),
+        }
+        let coinbase_count = block.content.transaction_detail.values().filter(|tx| is_coinbase(tx)).count();
+        if coinbase_count > 1 {
+            return Err(BlockValidationError::TooManyCoinbase);
+        }
+
+        if parent == self.tip {
+            self.check_block_transactions(block, &self.ledger_state)?;
+        }
+        Ok(())
+    }
+
+    /// Run `check_tx` on every non-coinbase transaction of the block in order, spending from a
+    /// copy of `state` so that double spends inside the block are caught too.
+    /// The coinbase has to commit to the block's height and may claim at most the subsidy at that
+    /// height plus the fees of the other transactions.
+    fn check_block_transactions(&self, block: &Block, state: &UtxoSet) -> Result<(), BlockValidationError> {
+        let height = self.chain.get(&block.header.parent_pointer).unwrap().content.height + 1;
+        let time = self.median_time_past(&block.header.parent_pointer);
+        let mut check_state = state.clone();
+        let mut fees: u64 = 0;
+        let mut claimed: u64 = 0;
+        for i in block.content.transactions.iter() {
+            let transac_now = block.content.transaction_detail.get(i).unwrap();
+            if is_coinbase(transac_now) {
+                if transac_now.tx.inputs[0].index != height {
+                    return Err(BlockValidationError::BadCoinbaseHeight { expected: height, found: transac_now.tx.inputs[0].index });
+                }
+                claimed += output_sum(&transac_now.tx);
+                continue;
+            }
+            if let Err(e) = check_tx(transac_now, &check_state, height, time, self.params.coinbase_maturity) {
+                return Err(BlockValidationError::InvalidTransaction(*i, e));
+            }
+            fees += tx_fee(transac_now, &check_state).unwrap();
+            check_state.apply_tx(transac_now, height, time);
+        }
+        let allowed = self.params.block_subsidy(height) as u64 + fees;
+        if claimed > allowed {
+            return Err(BlockValidationError::CoinbaseTooLarge { claimed: claimed, allowed: allowed });
+        }
+        Ok(())
+    }
+
+    /// The outputs `t` spends, from the ledger or from mempool transactions. Outputs of mempool
+    /// transactions count as created in the next block, so they cannot satisfy a relative lock.
+    pub fn spend_view(&self, t: &SignedTransaction) -> UtxoSet {
+        let mut view = UtxoSet::new();
+        let time = self.median_time_past(&self.tip);
+        for input in t.tx.inputs.iter() {
+            let outpoint = OutPoint::from(input);
+            if let Some(entry) = self.ledger_state.entry(&outpoint) {
+                view.add_entry(outpoint, entry.clone());
+            } else if let Some(output) = self.mempool.get(&input.prev_tx).and_then(|parent| parent.tx.outputs.get(input.index as usize)) {
+                view.add_entry(outpoint, UtxoEntry {
+                    output: output.clone(),
+                    height: self.longest_height + 1,
+                    time: time,
+                    is_coinbase: false,
+                });
+            }
+        }
+        view
+    }
+
+    /// Check that `t` can go into the mempool, i.e. be included in the next block on the tip,
+    /// possibly after the mempool transactions it spends
+    pub fn check_mempool_tx(&self, t: &SignedTransaction) -> Result<(), TxValidationError> {
+        let time = self.median_time_past(&self.tip);
+        check_tx(t, &self.spend_view(t), self.longest_height + 1, time, self.params.coinbase_maturity)
+    }
+
+    /// Check the parts of `t` that do not need its missing parents, see `check_tx_missprev`
+    pub fn check_orphan_tx(&self, t: &SignedTransaction) -> Result<(), TxValidationError> {
+        let time = self.median_time_past(&self.tip);
+        check_tx_missprev(t, &self.spend_view(t), self.longest_height + 1, time)
+    }
+
+    /// Transactions whose outputs `t` spends but that are neither in the ledger nor in the mempool
+    pub fn missing_parents(&self, t: &SignedTransaction) -> Vec<H256> {
+        let view = self.spend_view(t);
+        let mut parents = Vec::new();
+        for input in t.tx.inputs.iter() {
+            if !view.contains(&OutPoint::from(input)) && !parents.contains(&input.prev_tx) {
+                parents.push(input.prev_tx);
+            }
+        }
+        parents
+    }
+
+    /// Whether this node has seen the transaction `txid`: in the mempool or in a block of the longest chain
+    pub fn knows_tx(&self, txid: &H256) -> bool {
+        self.mempool.contains(txid) || self.confirmed_txs.contains(txid)
+    }
+
+    /// Validate `t` against the tip and add it to the mempool as arriving at `now` (milliseconds).
+    /// Returns the txids it replaced or that were evicted to make room. A transaction spending
+    /// outputs of transactions this node has never seen, that is otherwise fine, fails with
+    /// `MissingParents` so the caller can keep it as an orphan. Spending an output that is already
+    /// spent stays `Invalid(MissingInput)`.
+    pub fn add_to_mempool(&mut self, t: SignedTransaction, now: i64) -> Result<Vec<H256>, MempoolError> {
+        if self.mempool.contains(&t.txid()) {
+            return Err(MempoolError::AlreadyInPool);
+        }
+        if let Err(e) = self.check_mempool_tx(&t) {
+            if let TxValidationError::MissingInput(_) = e {
+                // 父tx见过，说明output已经被花，不是orphan
+                let parents = self.missing_parents(&t);
+                if !parents.iter().any(|parent| self.knows_tx(parent)) && self.check_orphan_tx(&t).is_ok() {
+                    return Err(MempoolError::MissingParents(parents));
+                }
+            }
+            return Err(MempoolError::Invalid(e));
+        }
+        let fee = tx_fee(&t, &self.spend_view(&t)).unwrap();
+        self.mempool.insert(MempoolEntry::new(t, fee, now))
+    }
+
+    /// Drop mempool transactions that arrived more than `mempool_expiry` before `now`
+    pub fn expire_mempool(&mut self, now: i64) {
+        self.mempool.expire(now - self.params.mempool_expiry);
+    }
+
+    /// Drop mempool transactions that can no longer go into the next block.
+    /// Only needed when the tip changes, the pool is checked against the tip on the way in.
+    fn revalidate_mempool(&mut self) {
+        for txid in self.mempool.txids() {
+            // 可能已随父tx一起移除
+            let valid = match self.mempool.get(&txid) {
+                Some(t) => self.check_mempool_tx(t).is_ok(),
+                None => continue,
+            };
+            if !valid {
+                self.mempool.remove_with_descendants(&txid);
+            }
+        }
+    }
+
+    /// Log a transaction this node refused and keep it in `recent_rejects`
+    pub fn record_reject(&mut self, hash: H256, error: MempoolError) {
+        warn!("Rejected transaction {:?}: {:?}", hash, error);
+        if self.recent_rejects.len() >= MAX_RECENT_REJECTS {
+            self.recent_rejects.pop_front();
+        }
+        self.recent_rejects.push_back((hash, error));
+    }
+
+    /// Median timestamp of the last `median_time_span` blocks ending at `hash`
+    pub fn median_time_past(&self, hash: &H256) -> i64 {
+        let mut times = Vec::new();
+        let mut cursor = self.chain.get(hash);
+        while let Some(block) = cursor {
+            times.push(block.header.timestamp);
+            if times.len() == self.params.median_time_span {
+                break;
+            }
+            cursor = self.chain.get(&block.header.parent_pointer);
+        }
+        times.sort();
+        times[times.len() / 2]
+    }
+
+    /// Connect a stored block on top of the current tip, applying its transactions to the ledger
+    /// and keeping the outputs it spends as undo data
+    fn connect_block(&mut self, hash: &H256) -> Result<(), BlockValidationError> {
+        let block = self.chain.get(hash).unwrap().clone();
+        self.check_block_transactions(&block, &self.ledger_state)?;
+        let time = self.median_time_past(&block.header.parent_pointer);
+        let mut spent: HashMap<OutPoint, UtxoEntry> = HashMap::new();
+        for i in block.content.transactions.iter() {
+            let transac_now = block.content.transaction_detail.get(i).unwrap();
+            // 花掉inputs，加入新outputs（包括矿工奖励），记录undo
+            spent.extend(self.ledger_state.apply_tx(transac_now, block.content.height, time));
+            self.mempool.remove_confirmed(transac_now);
+            self.confirmed_txs.insert(*i);
+        }
+        self.undo.insert(*hash, spent);
+        self.tip = *hash;
+        self.longest_chain.push(*hash);
+        self.longest_height = block.content.height;
+        Ok(())
+    }
+
+    /// Keep undo data only for the last `max_reorg_depth` blocks of the longest chain, the deepest
+    /// a reorg may reach, so the snapshot does not grow with the chain
+    fn prune_undo(&mut self) {
+        let keep_from = (self.longest_height + 1).saturating_sub(self.params.max_reorg_depth);
+        let chain = &self.chain;
+        self.undo.retain(|hash, _| chain.get(hash).map_or(false, |block| block.content.height >= keep_from));
+    }
+
+    /// Disconnect the current tip, restoring the ledger state to exactly what it was before the
+    /// block, and returning its transactions to the mempool if `restore_mempool` is set.
+    /// Returns the hash of the disconnected block, or `None` at genesis or if the block's undo
+    /// data has been pruned, leaving the chain unchanged.
+    pub fn disconnect_block(&mut self, restore_mempool: bool) -> Option<H256> {
+        if self.longest_height == 0 {
+            return None;
+        }
+        let old_tip = *self.longest_chain.last().unwrap();
+        let spent = match self.undo.remove(&old_tip) {
+            Some(spent) => spent,
+            None => {
+                error!("No undo data for block {:?}, cannot disconnect it", old_tip);
+                return None;
+            }
+        };
+        self.longest_chain.pop();
+        let old_tip_block = self.chain.get(&old_tip).unwrap().clone();
+        // 倒序撤销，块内后面的tx可能花了前面tx的output
+        for i in old_tip_block.content.transactions.iter().rev() {
+            let transac_now = old_tip_block.content.transaction_detail.get(i).unwrap();
+            self.ledger_state.undo_tx(transac_now, &spent);   //弹出新state，恢复被花的state
+            self.confirmed_txs.remove(i);
+        }
+        self.tip = old_tip_block.header.parent_pointer;
+        self.longest_height -= 1;
+        if restore_mempool {
+            // 按块内顺序放回，父tx在子tx之前，祖先/后代上限照常检查
+            for i in old_tip_block.content.transactions.iter() {
+                let transac_now = old_tip_block.content.transaction_detail.get(i).unwrap();
+                if !is_coinbase(transac_now) {
+                    self.restore_to_mempool(transac_now.clone(), old_tip_block.header.timestamp);
+                }
+            }
+        }
+        Some(old_tip)
+    }
+
+    /// Put a transaction of a disconnected block back into the mempool. It takes priority over
+    /// pool transactions spending the same outputs, which are removed with their descendants.
+    /// Anything that still does not fit is logged and dropped.
+    fn restore_to_mempool(&mut self, t: SignedTransaction, time: i64) {
+        let txid = t.txid();
+        for (_, other) in self.mempool.conflicts(&t) {
+            let removed = self.mempool.remove_with_descendants(&other);
+            warn!("Removed {:?} from mempool, conflicting with {:?} of a disconnected block", removed, txid);
+        }
+        // 刚撤销完inputs都在
+        let fee = match tx_fee(&t, &self.spend_view(&t)) {
+            Some(fee) => fee,
+            None => {
+                warn!("Dropped transaction {:?} of a disconnected block: inputs not found", txid);
+                return;
+            }
+        };
+        if let Err(e) = self.mempool.insert(MempoolEntry::new(t, fee, time)) {
+            warn!("Dropped transaction {:?} of a disconnected block: {:?}", txid, e);
+        }
+    }
+
+    /// Difficulty required for a child of `parent`.
+    /// Every `retarget_window` blocks the target is scaled by how long the previous window took
+    /// compared with `block_interval`, limited to a factor of 4 and never easier than genesis.
+    pub fn next_difficulty(&self, parent: &H256) -> H256 {
+        let parentblock = self.chain.get(parent).unwrap();
+        let window = self.params.retarget_window;
+        let height = parentblock.content.height + 1;
+        // 不到调整高度，沿用父块difficulty
+        if window == 0 || height % window != 0 {
+            return parentblock.header.difficulty;
+        }
+        // 往回window个块，找到窗口起点；第一个窗口从genesis开始，少一个间隔
+        let first_height = (height - 1).saturating_sub(window);
+        let intervals = height - 1 - first_height;
+        if intervals == 0 {
+            return parentblock.header.difficulty;
+        }
+        let first_hash = self.ancestor(parent, first_height).unwrap();
+        let first = self.chain.get(&first_hash).unwrap();
+        let expected = self.params.block_interval.saturating_mul(intervals as i64);
+        // 目标间隔不是正数时没法按比例调整
+        if expected <= 0 {
+            return parentblock.header.difficulty;
+        }
+        let actual = (parentblock.header.timestamp - first.header.timestamp)
+            .max(expected / 4)
+            .min(expected * 4)
+            .max(1);
+
+        let pow_limit = U256::from(&self.params.genesis_difficulty);
+        let (scaled, overflow) = U256::from(&parentblock.header.difficulty).overflowing_mul_u64(actual as u64);
+        if overflow {
+            return (&pow_limit).into();
+        }
+        let target = scaled.div_rem(&U256::from(expected as u64)).0;
+        if target > pow_limit {
+            (&pow_limit).into()
+        } else {
+            (&target).into()
+        }
     }
 
     /// Get the last block's hash of the longest chain
@@ -159,10 +739,49 @@ impl Blockchain {
         //unimplemented!()
     }
 
-    /// Get the last block's hash of the longest chain
-    #[cfg(any(test, test_utilities))]
+    /// Get all blocks' hashes of the longest chain, from genesis to tip
     pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
-        unimplemented!()
+        self.longest_chain.clone()
+    }
+
+    /// Hash of the block at `height` on the longest chain
+    pub fn block_at_height(&self, height: u32) -> Option<H256> {
+        self.longest_chain.get(height as usize).cloned()
+    }
+
+    /// Whether the block with this hash is part of the longest chain
+    pub fn is_in_main_chain(&self, hash: &H256) -> bool {
+        match self.chain.get(hash) {
+            Some(block) => self.block_at_height(block.content.height) == Some(*hash),
+            None => false,
+        }
+    }
+
+    /// The ancestor of `hash` at `height` (the block itself at its own height), on any branch
+    pub fn ancestor(&self, hash: &H256, height: u32) -> Option<H256> {
+        let mut cursor = *hash;
+        loop {
+            let block = self.chain.get(&cursor)?;
+            if block.content.height < height {
+                return None;
+            }
+            if block.content.height == height {
+                return Some(cursor);
+            }
+            // 走到最长链上后直接查索引
+            if self.is_in_main_chain(&cursor) {
+                return self.block_at_height(height);
+            }
+            cursor = block.header.parent_pointer;
+        }
+    }
+
+    /// Hashes of the longest chain blocks with heights in `range`
+    pub fn blocks_in_range(&self, range: std::ops::Range<u32>) -> std::slice::Iter<H256> {
+        let len = self.longest_chain.len() as u32;
+        let start = range.start.min(len) as usize;
+        let end = range.end.min(len).max(range.start.min(len)) as usize;
+        self.longest_chain[start..end].iter()
     }
 }
 
@@ -171,14 +790,752 @@ mod tests {
     use super::*;
     use crate::block::test::generate_random_block;
     use crate::crypto::hash::Hashable;
+    use crate::crypto::key_pair;
+    use crate::script::{Script, ScriptError};
+
+    fn signed_spend(prev_tx: &H256, value: u32, key: &Ed25519KeyPair) -> SignedTransaction {
+        let tx = Transaction{
+            inputs: vec![TxInput{prev_tx: *prev_tx, index: 0, unlock: Script::p2pkh_unlock(&pk_to_u8(key.public_key())), sequence: SEQUENCE_FINAL}],
+            outputs: vec![TxOutput{value: value, lock: Script::p2pkh(&[9;20].into())}],
+            lock_time: 0,
+        };
+        sign_tx(tx, &[key])
+    }
+
+    /// A new chain whose ledger has one output of `value` at `[7;32]:0`, spendable by the returned key
+    fn funded_chain(value: u32) -> (Blockchain, Ed25519KeyPair, H256) {
+        let mut blockchain = Blockchain::new();
+        let key = key_pair::random();
+        let coin: H256 = [7;32].into();
+        blockchain.ledger_state.add(OutPoint::new(coin, 0), TxOutput{value: value, lock: Script::p2pkh(&pk_to_h160(&pk_to_u8(key.public_key())))});
+        (blockchain, key, coin)
+    }
+
+    /// A block on top of the tip with a coinbase, the given transactions and a valid nonce
+    fn mine_block(blockchain: &Blockchain, txs: Vec<SignedTransaction>) -> Block {
+        let height = blockchain.chain.get(&blockchain.tip()).unwrap().content.height + 1;
+        mine_block_claiming(blockchain, blockchain.params.block_subsidy(height), txs)
+    }
+
+    /// Like `mine_block`, with a coinbase paying `reward`
+    fn mine_block_claiming(blockchain: &Blockchain, reward: u32, txs: Vec<SignedTransaction>) -> Block {
+        mine_block_paying(blockchain, &key_pair::random(), reward, txs)
+    }
+
+    /// Like `mine_block`, with a coinbase paying `reward` to `key`
+    fn mine_block_paying(blockchain: &Blockchain, key: &Ed25519KeyPair, reward: u32, txs: Vec<SignedTransaction>) -> Block {
+        let parent = blockchain.tip();
+        let mut block = generate_random_block(&parent);
+        let height = blockchain.chain.get(&parent).unwrap().content.height + 1;
+        let coinbase = bonus_tx(key, reward, height);
+        block.content.transactions.push(coinbase.txid());
+        block.content.transaction_detail.insert(coinbase.txid(), coinbase);
+        for tx in txs {
+            block.content.transactions.push(tx.txid());
+            block.content.transaction_detail.insert(tx.txid(), tx);
+        }
+        block.header.merkle_root = block.content.merkle_root();
+        block.header.difficulty = blockchain.next_difficulty(&parent);
+        solve(&mut block);
+        block
+    }
+
+    fn solve(block: &mut Block) {
+        while block.hash() > block.header.difficulty {
+            block.header.nonce = block.header.nonce.wrapping_add(1);
+        }
+    }
+
+    #[test]
+    fn insert_one() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let block = generate_random_block(&genesis_hash);
+        blockchain.insert(&block).unwrap();
+        assert_eq!(blockchain.tip(), block.hash());
+
+    }
+
+    #[test]
+    fn reorg_to_longer_branch() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let a1 = generate_random_block(&genesis_hash);
+        let a2 = generate_random_block(&a1.hash());
+        blockchain.insert(&a1).unwrap();
+        blockchain.insert(&a2).unwrap();
+        assert_eq!(blockchain.tip(), a2.hash());
+
+        let b1 = generate_random_block(&genesis_hash);
+        let b2 = generate_random_block(&b1.hash());
+        let b3 = generate_random_block(&b2.hash());
+        assert_eq!(blockchain.insert(&b1), Ok(Reorg::default()));
+        assert_eq!(blockchain.insert(&b2), Ok(Reorg::default()));
+        let reorg = blockchain.insert(&b3).unwrap();
+        assert_eq!(reorg.disconnected, vec![a2.hash(), a1.hash()]);
+        assert_eq!(reorg.connected, vec![b1.hash(), b2.hash(), b3.hash()]);
+        assert_eq!(blockchain.tip(), b3.hash());
+        assert_eq!(blockchain.longest_chain, vec![genesis_hash, b1.hash(), b2.hash(), b3.hash()]);
+    }
+
+    #[test]
+    fn invalid_branch_is_dropped() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        let genesis_hash = blockchain.tip();
+        let a1 = generate_random_block(&genesis_hash);
+        let a2 = generate_random_block(&a1.hash());
+        let a3 = generate_random_block(&a2.hash());
+        for block in vec![&a1, &a2, &a3] {
+            blockchain.insert(block).unwrap();
+        }
+
+        // 侧链上b1的tx有效，b2花了不存在的output，work不够时不会被检查
+        let mut b1 = generate_random_block(&genesis_hash);
+        let spend = signed_spend(&coin, 40, &key);
+        b1.content.transactions.push(spend.txid());
+        b1.content.transaction_detail.insert(spend.txid(), spend.clone());
+        let mut b2 = generate_random_block(&b1.hash());
+        let bad = signed_spend(&[8;32].into(), 10, &key);
+        b2.content.transactions.push(bad.txid());
+        b2.content.transaction_detail.insert(bad.txid(), bad.clone());
+        let b3 = generate_random_block(&b2.hash());
+        let sibling = generate_random_block(&b2.hash());
+        let b4 = generate_random_block(&b3.hash());
+        for block in vec![&b1, &b2, &b3, &sibling] {
+            assert_eq!(blockchain.insert(block), Ok(Reorg::default()));
+        }
+        // spend在池中，b1连上时会被当作已确认移走
+        assert_eq!(blockchain.add_to_mempool(spend.clone(), 1), Ok(vec![]));
+        let pool_before = blockchain.mempool.clone();
+        let ledger_before = blockchain.ledger_state.clone();
+        assert_eq!(blockchain.insert(&b4), Err(BlockValidationError::InvalidTransaction(bad.txid(), TxValidationError::MissingInput(OutPoint::new([8;32].into(), 0)))));
+        assert_eq!(blockchain.tip(), a3.hash());
+        // 切换失败后mempool原样恢复
+        assert!(blockchain.mempool.contains(&spend.txid()));
+        assert_eq!(blockchain.mempool, pool_before);
+        assert_eq!(blockchain.ledger_state, ledger_before);
+        assert!(blockchain.chain.contains_key(&b1.hash()));
+        for dropped in vec![&b2, &b3, &sibling, &b4] {
+            assert!(!blockchain.chain.contains_key(&dropped.hash()));
+            assert!(!blockchain.chain_work.contains_key(&dropped.hash()));
+        }
+
+        // 延长无效块下的另一个子块，不能再切换过去
+        let extended = generate_random_block(&sibling.hash());
+        assert_eq!(blockchain.insert(&extended), Err(BlockValidationError::UnknownParent(sibling.hash())));
+        assert_eq!(blockchain.tip(), a3.hash());
+    }
+
+    #[test]
+    fn undo_kept_for_reorg_depth() {
+        let mut blockchain = Blockchain::new();
+        blockchain.params.max_reorg_depth = 2;
+        let genesis_hash = blockchain.tip();
+        let mut parent = genesis_hash;
+        for _ in 0..4 {
+            let block = generate_random_block(&parent);
+            blockchain.insert(&block).unwrap();
+            parent = block.hash();
+        }
+        assert_eq!(blockchain.undo.len(), 2);
+        assert!(blockchain.undo.contains_key(&blockchain.longest_chain[3]) && blockchain.undo.contains_key(&blockchain.longest_chain[4]));
+
+        // 从高度2分叉可以回滚，从genesis分叉太深
+        let mut side = blockchain.longest_chain[2];
+        for _ in 0..3 {
+            let block = generate_random_block(&side);
+            blockchain.insert(&block).unwrap();
+            side = block.hash();
+        }
+        assert_eq!(blockchain.tip(), side);
+        let mut deep = genesis_hash;
+        for _ in 0..5 {
+            let block = generate_random_block(&deep);
+            assert_eq!(blockchain.insert(&block), Ok(Reorg::default()));
+            deep = block.hash();
+        }
+        assert_eq!(blockchain.insert(&generate_random_block(&deep)), Err(BlockValidationError::ReorgTooDeep { fork_height: 0 }));
+        assert_eq!(blockchain.tip(), side);
+    }
+
+    #[test]
+    fn failed_reorg_keeps_undo() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        blockchain.params.max_reorg_depth = 3;
+        let genesis_hash = blockchain.tip();
+        let mut a1 = generate_random_block(&genesis_hash);
+        let spend = signed_spend(&coin, 40, &key);
+        a1.content.transactions.push(spend.txid());
+        a1.content.transaction_detail.insert(spend.txid(), spend);
+        let a2 = generate_random_block(&a1.hash());
+        // a3的work很大，b3、b4接上也不会切换
+        let mut a3 = generate_random_block(&a2.hash());
+        a3.header.difficulty = (hex!("0000050000000000000000000000000000000000000000000000000000000000")).into();
+        for block in vec![&a1, &a2, &a3] {
+            blockchain.insert(block).unwrap();
+        }
+
+        // b5无效，切到b分支时b4已经连上（高度4），但a1的undo不能因此被裁掉
+        let b3 = generate_random_block(&a2.hash());
+        let b4 = generate_random_block(&b3.hash());
+        let mut b5 = generate_random_block(&b4.hash());
+        let bad = signed_spend(&[8;32].into(), 10, &key);
+        b5.content.transactions.push(bad.txid());
+        b5.content.transaction_detail.insert(bad.txid(), bad);
+        b5.header.difficulty = (hex!("0000000500000000000000000000000000000000000000000000000000000000")).into();
+        for block in vec![&b3, &b4] {
+            assert_eq!(blockchain.insert(block), Ok(Reorg::default()));
+        }
+        assert!(blockchain.insert(&b5).is_err());
+        assert_eq!(blockchain.tip(), a3.hash());
+        assert!(blockchain.undo.contains_key(&a1.hash()));
+
+        // 从genesis分叉的更重分支能正确回滚a1花掉的output
+        let mut c1 = generate_random_block(&genesis_hash);
+        c1.header.difficulty = (hex!("0000000005000000000000000000000000000000000000000000000000000000")).into();
+        let mut without_undo = blockchain.clone();
+        blockchain.insert(&c1).unwrap();
+        assert_eq!(blockchain.tip(), c1.hash());
+        assert!(blockchain.ledger_state.contains(&OutPoint::new(coin, 0)));
+
+        // 缺undo时拒绝切换，而不是悄悄弄坏账本
+        without_undo.undo.remove(&a1.hash());
+        assert_eq!(without_undo.insert(&c1), Err(BlockValidationError::ReorgTooDeep { fork_height: 0 }));
+        assert_eq!(without_undo.tip(), a3.hash());
+        assert_eq!(without_undo.disconnect_block(true), Some(a3.hash()));
+        assert_eq!(without_undo.disconnect_block(true), Some(a2.hash()));
+        assert_eq!(without_undo.disconnect_block(true), None);
+        assert_eq!(without_undo.tip(), a1.hash());
+    }
+
+    #[test]
+    fn disconnect_restores_ledger() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let key = key_pair::random();
+        let prev_hash: H256 = [7;32].into();
+        let prev_output = TxOutput{value: 50, lock: Script::p2pkh(&pk_to_h160(&pk_to_u8(key.public_key())))};
+        blockchain.ledger_state.add(OutPoint::new(prev_hash, 0), prev_output.clone());
+        let before = blockchain.ledger_state.clone();
+
+        let spend = signed_spend(&prev_hash, 50, &key);
+        let mut block = generate_random_block(&genesis_hash);
+        block.content.transactions.push(spend.txid());
+        block.content.transaction_detail.insert(spend.txid(), spend.clone());
+
+        blockchain.insert(&block).unwrap();
+        assert!(!blockchain.ledger_state.contains(&OutPoint::new(prev_hash, 0)));
+        assert!(blockchain.ledger_state.contains(&OutPoint::new(spend.txid(), 0)));
+
+        assert_eq!(blockchain.disconnect_block(true), Some(block.hash()));
+        assert_eq!(blockchain.ledger_state, before);
+        assert_eq!(blockchain.tip(), genesis_hash);
+        assert!(blockchain.mempool.contains(&spend.txid()));
+        assert_eq!(blockchain.disconnect_block(true), None);
+    }
+
+    #[test]
+    fn multi_input_output_spend() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let key = key_pair::random();
+        let owner = pk_to_h160(&pk_to_u8(key.public_key()));
+        let coins: Vec<H256> = vec![[7;32].into(), [8;32].into()];
+        for coin in coins.iter() {
+            blockchain.ledger_state.add(OutPoint::new(*coin, 0), TxOutput{value: 30, lock: Script::p2pkh(&owner)});
+        }
+        let before = blockchain.ledger_state.clone();
+
+        let signed = |tx: Transaction| sign_tx(tx, &[&key]);
+        let inputs: Vec<TxInput> = coins.iter().map(|coin| TxInput{prev_tx: *coin, index: 0, unlock: Script::p2pkh_unlock(&pk_to_u8(key.public_key())), sequence: SEQUENCE_FINAL}).collect();
+        let overspend = signed(Transaction{
+            inputs: inputs.clone(),
+            outputs: vec![TxOutput{value: 61, lock: Script::p2pkh(&[9;20].into())}],
+            lock_time: 0,
+        });
+        assert_eq!(blockchain.check_mempool_tx(&overspend), Err(TxValidationError::Overspend { input: 60, output: 61 }));
+        let duplicate = signed(Transaction{
+            inputs: vec![inputs[0].clone(), inputs[0].clone()],
+            outputs: vec![TxOutput{value: 40, lock: Script::p2pkh(&[9;20].into())}],
+            lock_time: 0,
+        });
+        assert_eq!(blockchain.check_mempool_tx(&duplicate), Err(TxValidationError::DuplicateInput(OutPoint::new(coins[0], 0))));
+        // 别人的key打不开p2pkh脚本
+        let thief = key_pair::random();
+        let stolen = signed_spend(&coins[0], 30, &thief);
+        assert_eq!(blockchain.check_mempool_tx(&stolen), Err(TxValidationError::ScriptFailed {
+            outpoint: OutPoint::new(coins[0], 0),
+            error: ScriptError::VerifyFailed,
+        }));
+
+        // 付40，找零20
+        let spend = signed(Transaction{
+            inputs: inputs,
+            outputs: vec![TxOutput{value: 40, lock: Script::p2pkh(&[9;20].into())}, TxOutput{value: 20, lock: Script::p2pkh(&owner)}],
+            lock_time: 0,
+        });
+        assert!(blockchain.check_mempool_tx(&spend).is_ok());
+        let mut block = generate_random_block(&genesis_hash);
+        block.content.transactions.push(spend.txid());
+        block.content.transaction_detail.insert(spend.txid(), spend.clone());
+        blockchain.insert(&block).unwrap();
+        assert!(!blockchain.ledger_state.contains(&OutPoint::new(coins[0], 0)));
+        assert!(!blockchain.ledger_state.contains(&OutPoint::new(coins[1], 0)));
+        assert_eq!(blockchain.ledger_state.get(&OutPoint::new(spend.txid(), 0)).unwrap().value, 40);
+        assert_eq!(blockchain.ledger_state.get(&OutPoint::new(spend.txid(), 1)).unwrap().value, 20);
+
+        blockchain.disconnect_block(true);
+        assert_eq!(blockchain.ledger_state, before);
+    }
 
-    //#[test]
-    //fn insert_one() {
-        //let mut blockchain = Blockchain::new();
-        //let genesis_hash = blockchain.tip();
-        //let block = generate_random_block(&genesis_hash);
-        //blockchain.insert(&block);
-        //assert_eq!(blockchain.tip(), block.hash());
+    #[test]
+    fn mempool_conflicts() {
+        let (mut blockchain, key, coin) = funded_chain(50);
 
-    //}
+        let first = signed_spend(&coin, 40, &key);
+        let second = signed_spend(&coin, 30, &key);
+        assert_eq!(blockchain.add_to_mempool(first.clone(), 0), Ok(vec![]));
+        assert_eq!(blockchain.add_to_mempool(first.clone(), 1), Err(MempoolError::AlreadyInPool));
+        assert_eq!(blockchain.add_to_mempool(second.clone(), 1), Err(MempoolError::Conflict {
+            outpoint: OutPoint::new(coin, 0),
+            spender: first.txid(),
+        }));
+        assert_eq!(blockchain.mempool.entry(&first.txid()).unwrap().fee, 10);
+
+        // 块里确认的是second，first随之移出mempool
+        let block = mine_block(&blockchain, vec![second.clone()]);
+        blockchain.insert(&block).unwrap();
+        assert!(blockchain.mempool.is_empty());
+
+        // 撤销块时，块里的second优先于池中与它冲突的first
+        blockchain.mempool.insert(MempoolEntry::new(first.clone(), 10, 2)).unwrap();
+        blockchain.disconnect_block(true);
+        assert_eq!(blockchain.mempool.txids(), vec![second.txid()]);
+        assert_eq!(blockchain.mempool.entry(&second.txid()).unwrap().fee, 20);
+    }
+
+    #[test]
+    fn mempool_replace_by_fee() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        let replaceable = |value: u32| {
+            let mut t = signed_spend(&coin, value, &key).tx;
+            t.inputs[0].sequence = SEQUENCE_RBF_MAX;
+            sign_tx(t, &[&key])
+        };
+
+        let first = replaceable(45);
+        assert_eq!(blockchain.add_to_mempool(first.clone(), 0), Ok(vec![]));
+        // 换个收款方、手续费一样，不能替换
+        let mut same = first.tx.clone();
+        same.outputs[0].lock = Script::p2pkh(&[8;20].into());
+        let same = sign_tx(same, &[&key]);
+        match blockchain.add_to_mempool(same, 1) {
+            Err(MempoolError::ReplacementFeeTooLow { fee: 5, replaced_fee: 5, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let second = replaceable(30);
+        assert_eq!(blockchain.add_to_mempool(second.clone(), 2), Ok(vec![first.txid()]));
+        assert_eq!(blockchain.mempool.txids(), vec![second.txid()]);
+        assert_eq!(blockchain.mempool.entry(&second.txid()).unwrap().fee, 20);
+    }
+
+    #[test]
+    fn chained_mempool_spend() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        let address = pk_to_h160(&pk_to_u8(key.public_key()));
+
+        // parent付给自己，child花parent还没确认的output
+        let parent = sign_tx(Transaction{
+            inputs: vec![TxInput{prev_tx: coin, index: 0, unlock: Script::p2pkh_unlock(&pk_to_u8(key.public_key())), sequence: SEQUENCE_FINAL}],
+            outputs: vec![TxOutput{value: 49, lock: Script::p2pkh(&address)}],
+            lock_time: 0,
+        }, &[&key]);
+        let child = signed_spend(&parent.txid(), 40, &key);
+        assert_eq!(blockchain.add_to_mempool(child.clone(), 0), Err(MempoolError::MissingParents(vec![parent.txid()])));
+        assert_eq!(blockchain.add_to_mempool(parent.clone(), 0), Ok(vec![]));
+        assert_eq!(blockchain.add_to_mempool(child.clone(), 1), Ok(vec![]));
+        assert_eq!(blockchain.mempool.entry(&child.txid()).unwrap().fee, 9);
+        assert_eq!(blockchain.mempool.packages_by_ancestor_fee_rate(), vec![vec![parent.txid(), child.txid()]]);
+
+        let block = mine_block(&blockchain, vec![parent.clone(), child.clone()]);
+        blockchain.insert(&block).unwrap();
+        assert!(blockchain.mempool.is_empty());
+        assert_eq!(blockchain.ledger_state.get(&OutPoint::new(child.txid(), 0)).unwrap().value, 40);
+
+        // 撤销块后两笔都回到mempool，child仍然有效
+        blockchain.disconnect_block(true);
+        assert!(blockchain.mempool.contains(&parent.txid()) && blockchain.mempool.contains(&child.txid()));
+        assert!(blockchain.check_mempool_tx(&child).is_ok());
+        assert_eq!(blockchain.mempool.entry(&child.txid()).unwrap().fee, 9);
+        assert_eq!(blockchain.mempool.packages_by_ancestor_fee_rate(), vec![vec![parent.txid(), child.txid()]]);
+    }
+
+    #[test]
+    fn orphan_tx_waits_for_parent() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        let missing: H256 = [8;32].into();
+        let spend = signed_spend(&missing, 40, &key);
+        assert_eq!(blockchain.add_to_mempool(spend.clone(), 0), Err(MempoolError::MissingParents(vec![missing])));
+        // 缺父tx也要先验签名，改过的tx直接拒绝
+        let mut tampered = spend.clone();
+        tampered.tx.outputs[0].value = 50;
+        assert_eq!(blockchain.add_to_mempool(tampered, 0), Err(MempoolError::Invalid(TxValidationError::BadSignature)));
+
+        // 父tx到了，和coin付给同一个key
+        let output = blockchain.ledger_state.get(&OutPoint::new(coin, 0)).unwrap().clone();
+        blockchain.ledger_state.add(OutPoint::new(missing, 0), output);
+        assert!(blockchain.missing_parents(&spend).is_empty());
+        assert_eq!(blockchain.add_to_mempool(spend.clone(), 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn double_spend_is_not_orphan() {
+        let (mut blockchain, key, coin) = funded_chain(50);
+        let address = pk_to_h160(&pk_to_u8(key.public_key()));
+        let parent = sign_tx(Transaction{
+            inputs: vec![TxInput{prev_tx: coin, index: 0, unlock: Script::p2pkh_unlock(&pk_to_u8(key.public_key())), sequence: SEQUENCE_FINAL}],
+            outputs: vec![TxOutput{value: 50, lock: Script::p2pkh(&address)}],
+            lock_time: 0,
+        }, &[&key]);
+        let spend = signed_spend(&parent.txid(), 40, &key);
+        let block = mine_block(&blockchain, vec![parent.clone(), spend]);
+        blockchain.insert(&block).unwrap();
+
+        // parent已确认，它的output已经被花，再花一次是无效而不是orphan
+        let double = signed_spend(&parent.txid(), 30, &key);
+        assert!(blockchain.knows_tx(&parent.txid()));
+        assert_eq!(blockchain.add_to_mempool(double, 1), Err(MempoolError::Invalid(TxValidationError::MissingInput(OutPoint::new(parent.txid(), 0)))));
+
+        // 块被断开后不再算已确认
+        blockchain.disconnect_block(false);
+        assert!(!blockchain.knows_tx(&parent.txid()));
+    }
+
+    #[test]
+    fn multisig_spend() {
+        let mut blockchain = Blockchain::new();
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let pks: Vec<[u8;32]> = keys.iter().map(|key| pk_to_u8(key.public_key())).collect();
+        let treasury = OutPoint::new([7;32].into(), 0);
+        blockchain.ledger_state.add(treasury, TxOutput{value: 50, lock: Script::multisig(2, &pks)});
+
+        let tx = Transaction{
+            inputs: vec![TxInput{prev_tx: treasury.txid, index: 0, unlock: Script::default(), sequence: SEQUENCE_FINAL}],
+            outputs: vec![TxOutput{value: 50, lock: Script::p2pkh(&[9;20].into())}],
+            lock_time: 0,
+        };
+        let one = sign_tx(tx.clone(), &[&keys[1]]);
+        assert_eq!(blockchain.check_mempool_tx(&one), Err(TxValidationError::ScriptFailed {
+            outpoint: treasury,
+            error: ScriptError::FalseResult,
+        }));
+        let two = sign_tx(tx.clone(), &[&keys[1], &keys[2]]);
+        assert!(blockchain.check_mempool_tx(&two).is_ok());
+        // 签名一个不对就整笔拒绝
+        let mut bad = two.clone();
+        bad.signatures[1].signature1 = [0;32];
+        assert_eq!(blockchain.check_mempool_tx(&bad), Err(TxValidationError::BadSignature));
+        let unsigned = SignedTransaction{tx: tx, signatures: vec![]};
+        assert_eq!(blockchain.check_mempool_tx(&unsigned), Err(TxValidationError::NoSignatures));
+    }
+
+    #[test]
+    fn timelocks() {
+        let mut blockchain = Blockchain::new();
+        let key = key_pair::random();
+        let pk = pk_to_u8(key.public_key());
+        let coin = OutPoint::new([7;32].into(), 0);
+        let output = TxOutput{value: 50, lock: Script::p2pkh(&pk_to_h160(&pk))};
+        let time = blockchain.median_time_past(&blockchain.tip());
+        blockchain.ledger_state.add_entry(coin, UtxoEntry{output: output, height: 0, time: time, is_coinbase: false});
+        let spend = |lock_time: u64, sequence: u32| sign_tx(Transaction{
+            inputs: vec![TxInput{prev_tx: coin.txid, index: 0, unlock: Script::p2pkh_unlock(&pk), sequence: sequence}],
+            outputs: vec![TxOutput{value: 50, lock: Script::p2pkh(&[9;20].into())}],
+            lock_time: lock_time,
+        }, &[&key]);
+
+        // 下一个块高度1
+        assert_eq!(blockchain.check_mempool_tx(&spend(2, 0xffff_fffe)), Err(TxValidationError::NotFinal { lock_time: 2 }));
+        assert!(blockchain.check_mempool_tx(&spend(2, SEQUENCE_FINAL)).is_ok());
+        assert_eq!(blockchain.check_mempool_tx(&spend(4_000_000_000_000, 0xffff_fffe)), Err(TxValidationError::NotFinal { lock_time: 4_000_000_000_000 }));
+        // genesis时间戳也是毫秒，锁到genesis时间的交易已经可以打包
+        assert!(blockchain.check_mempool_tx(&spend(blockchain.params.genesis_timestamp as u64, 0xffff_fffe)).is_ok());
+        assert_eq!(blockchain.check_mempool_tx(&spend(0, 2)), Err(TxValidationError::SequenceLocked(coin)));
+        assert!(blockchain.check_mempool_tx(&spend(0, 2 | SEQUENCE_DISABLE_FLAG)).is_ok());
+        assert_eq!(blockchain.check_mempool_tx(&spend(0, 1 | SEQUENCE_TYPE_FLAG)), Err(TxValidationError::SequenceLocked(coin)));
+
+        let block = mine_block(&blockchain, vec![]);
+        blockchain.insert(&block).unwrap();
+        // 下一个块高度2
+        assert!(blockchain.check_mempool_tx(&spend(2, 0xffff_fffe)).is_ok());
+        assert!(blockchain.check_mempool_tx(&spend(0, 2)).is_ok());
+        assert_eq!(blockchain.check_mempool_tx(&spend(0, 3)), Err(TxValidationError::SequenceLocked(coin)));
+    }
+
+    #[test]
+    fn most_work_wins() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let a1 = generate_random_block(&genesis_hash);
+        let a2 = generate_random_block(&a1.hash());
+        blockchain.insert(&a1).unwrap();
+        blockchain.insert(&a2).unwrap();
+
+        // 同样work、时间更早的块不能抢走tip
+        let mut b1 = generate_random_block(&genesis_hash);
+        b1.header.difficulty = (hex!("7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")).into();
+        b1.header.timestamp = 0;
+        assert_eq!(block_work(&b1.header.difficulty), U256::from(2));
+        blockchain.insert(&b1).unwrap();
+        assert_eq!(blockchain.tip(), a2.hash());
+
+        // 更矮但work更多的分支胜出
+        let mut c1 = generate_random_block(&genesis_hash);
+        c1.header.difficulty = (hex!("3fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")).into();
+        let reorg = blockchain.insert(&c1).unwrap();
+        assert_eq!(reorg.connected, vec![c1.hash()]);
+        assert_eq!(blockchain.tip(), c1.hash());
+        assert_eq!(blockchain.longest_height, 1);
+    }
+
+    #[test]
+    fn retarget_difficulty() {
+        let mut blockchain = Blockchain::new();
+        blockchain.params.retarget_window = 2;
+        blockchain.params.block_interval = 1000;
+        let genesis_diff = blockchain.chain.get(&blockchain.tip()).unwrap().header.difficulty;
+        let genesis_time = blockchain.params.genesis_timestamp;
+        let mut parent = blockchain.tip();
+        for &offset in [1000, 1250, 1500].iter() {
+            let mut block = generate_random_block(&parent);
+            block.header.difficulty = blockchain.next_difficulty(&parent);
+            block.header.timestamp = genesis_time + offset;
+            blockchain.insert(&block).unwrap();
+            parent = block.hash();
+        }
+        // 第2块：第一个窗口从genesis算起，按时出块，不变
+        assert_eq!(blockchain.chain.get(&parent).unwrap().header.difficulty, genesis_diff);
+        // 第4块：上个窗口只用了500ms，期望2000ms，目标变为1/4
+        let expected: H256 = (hex!("0001400000000000000000000000000000000000000000000000000000000000")).into();
+        assert_eq!(blockchain.next_difficulty(&parent), expected);
+
+        // 出块太慢时不会比genesis更容易
+        let mut slow = generate_random_block(&blockchain.chain.get(&parent).unwrap().header.parent_pointer);
+        slow.header.timestamp = genesis_time + 1000000;
+        blockchain.insert(&slow).unwrap();
+        assert_eq!(blockchain.next_difficulty(&slow.hash()), genesis_diff);
+
+        // 目标间隔不合法时沿用父块difficulty，不会除以0
+        for &interval in [0, -1000].iter() {
+            blockchain.params.block_interval = interval;
+            assert_eq!(blockchain.next_difficulty(&parent), genesis_diff);
+        }
+    }
+
+    #[test]
+    fn validate_mined_block() {
+        // main网络的块大小上限要装得下普通交易
+        let mut blockchain = Blockchain::new();
+        let key = key_pair::random();
+        let prev_hash: H256 = [7;32].into();
+        blockchain.ledger_state.add(OutPoint::new(prev_hash, 0), TxOutput{value: 50, lock: Script::p2pkh(&pk_to_h160(&pk_to_u8(key.public_key())))});
+
+        let block = mine_block(&blockchain, vec![signed_spend(&prev_hash, 50, &key)]);
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+        blockchain.insert(&block).unwrap();
+
+        // 同一个input不能再花
+        let double_spend = signed_spend(&prev_hash, 40, &key);
+        let block = mine_block(&blockchain, vec![double_spend.clone()]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockValidationError::InvalidTransaction(double_spend.txid(), TxValidationError::MissingInput(OutPoint::new(prev_hash, 0))))
+        );
+    }
+
+    #[test]
+    fn coinbase_claims_fees() {
+        let mut blockchain = Blockchain::new();
+        let key = key_pair::random();
+        let prev_hash: H256 = [7;32].into();
+        blockchain.ledger_state.add(OutPoint::new(prev_hash, 0), TxOutput{value: 50, lock: Script::p2pkh(&pk_to_h160(&pk_to_u8(key.public_key())))});
+        let spend = signed_spend(&prev_hash, 40, &key);
+        assert_eq!(tx_fee(&spend, &blockchain.ledger_state), Some(10));
+
+        let reward = blockchain.params.block_subsidy(1);
+        let greedy = mine_block_claiming(&blockchain, reward + 11, vec![spend.clone()]);
+        assert_eq!(
+            blockchain.validate_block(&greedy),
+            Err(BlockValidationError::CoinbaseTooLarge { claimed: reward as u64 + 11, allowed: reward as u64 + 10 })
+        );
+        let block = mine_block_claiming(&blockchain, reward + 10, vec![spend]);
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+
+        // 奖励加手续费记入ledger，手续费没有凭空消失
+        let before = blockchain.ledger_state.total_value();
+        blockchain.insert(&block).unwrap();
+        let coinbase = block.content.transactions[0];
+        assert_eq!(blockchain.ledger_state.get(&OutPoint::new(coinbase, 0)).unwrap().value, reward + 10);
+        assert_eq!(blockchain.ledger_state.total_value(), before + reward as u64);
+    }
+
+    #[test]
+    fn circulating_matches_issued() {
+        // /blockchain/supply报告的circulating应与issued一致
+        let mut blockchain = Blockchain::new();
+        blockchain.params.halving_interval = 2;
+        for _ in 0..5 {
+            let block = mine_block(&blockchain, vec![]);
+            blockchain.insert(&block).unwrap();
+        }
+        assert_eq!(blockchain.longest_height, 5);
+        assert_eq!(blockchain.ledger_state.total_value(), blockchain.params.issued_at_height(5));
+    }
+
+    #[test]
+    fn coinbase_maturity() {
+        let mut blockchain = Blockchain::new();
+        blockchain.params.coinbase_maturity = 2;
+        let key = key_pair::random();
+        let reward = blockchain.params.block_subsidy(1);
+        let block = mine_block_paying(&blockchain, &key, reward, vec![]);
+        blockchain.insert(&block).unwrap();
+        let coinbase = block.content.transactions[0];
+        let entry = blockchain.ledger_state.entry(&OutPoint::new(coinbase, 0)).unwrap().clone();
+        assert!(entry.is_coinbase);
+        assert_eq!(entry.height, 1);
+
+        // 高度2还不能花，高度3可以
+        let spend = signed_spend(&coinbase, reward, &key);
+        let immature = TxValidationError::ImmatureCoinbase { outpoint: OutPoint::new(coinbase, 0), spendable_at: 3 };
+        assert_eq!(blockchain.check_mempool_tx(&spend), Err(immature.clone()));
+        let early = mine_block(&blockchain, vec![spend.clone()]);
+        assert_eq!(blockchain.validate_block(&early), Err(BlockValidationError::InvalidTransaction(spend.txid(), immature)));
+        blockchain.insert(&mine_block(&blockchain, vec![])).unwrap();
+        assert!(blockchain.check_mempool_tx(&spend).is_ok());
+
+        // 断开块后coinbase的output也消失
+        blockchain.disconnect_block(true);
+        blockchain.disconnect_block(true);
+        assert!(!blockchain.ledger_state.contains(&OutPoint::new(coinbase, 0)));
+        assert!(blockchain.mempool.is_empty());
+    }
+
+    #[test]
+    fn rejects_are_bounded() {
+        let mut blockchain = Blockchain::new();
+        for i in 0..MAX_RECENT_REJECTS + 5 {
+            blockchain.record_reject([i as u8; 32].into(), MempoolError::Invalid(TxValidationError::BadSignature));
+        }
+        assert_eq!(blockchain.recent_rejects.len(), MAX_RECENT_REJECTS);
+        assert_eq!(blockchain.recent_rejects.front().unwrap().0, [5u8; 32].into());
+    }
+
+    #[test]
+    fn reject_malformed_blocks() {
+        let blockchain = Blockchain::new();
+        let block = mine_block(&blockchain, vec![]);
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+
+        let mut bad_root = block.clone();
+        bad_root.header.merkle_root = H256::default();
+        solve(&mut bad_root);
+        assert_eq!(blockchain.validate_block(&bad_root), Err(BlockValidationError::BadMerkleRoot));
+
+        let extra_coinbase = bonus_tx(&key_pair::random(), blockchain.params.block_subsidy(1), 1);
+        let two_coinbase = mine_block(&blockchain, vec![extra_coinbase]);
+        assert_eq!(blockchain.validate_block(&two_coinbase), Err(BlockValidationError::TooManyCoinbase));
+
+        let mut wrong_height = mine_block(&blockchain, vec![]);
+        let coinbase = bonus_tx(&key_pair::random(), blockchain.params.block_subsidy(1), 7);
+        wrong_height.content.transaction_detail.clear();
+        wrong_height.content.transactions = vec![coinbase.txid()];
+        wrong_height.content.transaction_detail.insert(coinbase.txid(), coinbase);
+        wrong_height.header.merkle_root = wrong_height.content.merkle_root();
+        solve(&mut wrong_height);
+        assert_eq!(blockchain.validate_block(&wrong_height), Err(BlockValidationError::BadCoinbaseHeight { expected: 1, found: 7 }));
+
+        let mut easy = block.clone();
+        easy.header.difficulty = [255;32].into();
+        assert!(matches!(blockchain.validate_block(&easy), Err(BlockValidationError::BadDifficulty { .. })));
+
+        let mut future = block.clone();
+        future.header.timestamp += 3 * blockchain.params.max_future_block_time;
+        solve(&mut future);
+        assert!(matches!(blockchain.validate_block(&future), Err(BlockValidationError::TimestampTooNew { .. })));
+    }
+
+    #[test]
+    fn restart_from_store() {
+        let dir = crate::store::tests::temp_dir();
+        let mut blockchain = Blockchain::new();
+        blockchain.open_store(&dir).unwrap();
+        let key = key_pair::random();
+        let prev_hash: H256 = [7;32].into();
+        blockchain.ledger_state.add(OutPoint::new(prev_hash, 0), TxOutput{value: 50, lock: Script::p2pkh(&pk_to_h160(&pk_to_u8(key.public_key())))});
+        let first = mine_block(&blockchain, vec![signed_spend(&prev_hash, 50, &key)]);
+        blockchain.insert(&first).unwrap();
+        let second = mine_block(&blockchain, vec![]);
+        blockchain.insert(&second).unwrap();
+
+        let mut restarted = Blockchain::new();
+        restarted.open_store(&dir).unwrap();
+        assert_eq!(restarted.tip(), second.hash());
+        assert_eq!(restarted.longest_chain, blockchain.longest_chain);
+        assert_eq!(restarted.ledger_state, blockchain.ledger_state);
+        assert_eq!(restarted.confirmed_txs, blockchain.confirmed_txs);
+        assert_eq!(restarted.disconnect_block(true), Some(second.hash()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_tip_must_connect() {
+        let dir = crate::store::tests::temp_dir();
+        let mut store = BlockStore::open(&dir).unwrap();
+        let stray = generate_random_block(&[9;32].into());
+        store.put(&stray).unwrap();
+        let snapshot = ChainSnapshot {
+            tip: stray.hash(),
+            ledger_state: UtxoSet::new(),
+            undo: HashMap::new(),
+            mempool: Vec::new(),
+        };
+        store.save_snapshot(&snapshot).unwrap();
+        store.set_tip(&stray.hash()).unwrap();
+
+        // 快照tip接不上genesis，退回重放，留在genesis
+        let mut restarted = Blockchain::new();
+        let genesis_hash = restarted.tip();
+        restarted.open_store(&dir).unwrap();
+        assert_eq!(restarted.tip(), genesis_hash);
+        assert_eq!(restarted.longest_chain, vec![genesis_hash]);
+        assert!(!restarted.chain.contains_key(&stray.hash()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn height_index_queries() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let a1 = generate_random_block(&genesis_hash);
+        let a2 = generate_random_block(&a1.hash());
+        let a3 = generate_random_block(&a2.hash());
+        let b2 = generate_random_block(&a1.hash());
+        let b3 = generate_random_block(&b2.hash());
+        for block in [&a1, &a2, &a3, &b2, &b3].iter() {
+            blockchain.insert(block).unwrap();
+        }
+
+        assert_eq!(blockchain.all_blocks_in_longest_chain(), vec![genesis_hash, a1.hash(), a2.hash(), a3.hash()]);
+        assert_eq!(blockchain.block_at_height(2), Some(a2.hash()));
+        assert_eq!(blockchain.block_at_height(4), None);
+        assert!(blockchain.is_in_main_chain(&a3.hash()));
+        assert!(!blockchain.is_in_main_chain(&b3.hash()));
+        assert_eq!(blockchain.ancestor(&b3.hash(), 2), Some(b2.hash()));
+        assert_eq!(blockchain.ancestor(&b3.hash(), 0), Some(genesis_hash));
+        assert_eq!(blockchain.ancestor(&b3.hash(), 4), None);
+        let range: Vec<H256> = blockchain.blocks_in_range(1..3).cloned().collect();
+        assert_eq!(range, vec![a1.hash(), a2.hash()]);
+        assert_eq!(blockchain.blocks_in_range(3..10).count(), 1);
+    }
 }
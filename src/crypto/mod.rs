@@ -1,3 +1,4 @@
 pub mod hash;
 pub mod merkle;
 pub mod key_pair;
+pub mod u256;
@@ -0,0 +1,191 @@
+use serde::{Serialize, Deserialize};
+
+use super::hash::H256;
+
+/// An unsigned 256-bit integer, used for difficulty targets and chain work.
+#[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Clone, Default, Copy, Debug)]
+pub struct U256([u64; 4]); // little endian limbs, [0] is the lowest
+
+impl U256 {
+    pub fn zero() -> U256 {
+        U256([0; 4])
+    }
+
+    pub fn max_value() -> U256 {
+        U256([u64::MAX; 4])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|limb| *limb == 0)
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        (self.0[i / 64] >> (i % 64)) & 1 == 1
+    }
+
+    fn set_bit(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    /// Shift left by one bit, returning the bit shifted out of the top.
+    fn shl1(&self) -> (U256, bool) {
+        let mut out = [0u64; 4];
+        let mut carry = 0;
+        for i in 0..4 {
+            out[i] = (self.0[i] << 1) | carry;
+            carry = self.0[i] >> 63;
+        }
+        (U256(out), carry == 1)
+    }
+
+    pub fn overflowing_add(&self, other: &U256) -> (U256, bool) {
+        let mut out = [0u64; 4];
+        let mut carry = false;
+        for i in 0..4 {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            out[i] = sum;
+            carry = c1 || c2;
+        }
+        (U256(out), carry)
+    }
+
+    pub fn overflowing_sub(&self, other: &U256) -> (U256, bool) {
+        let mut out = [0u64; 4];
+        let mut borrow = false;
+        for i in 0..4 {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            out[i] = diff;
+            borrow = b1 || b2;
+        }
+        (U256(out), borrow)
+    }
+
+    /// Add, clamping at the maximum value instead of wrapping.
+    pub fn saturating_add(&self, other: &U256) -> U256 {
+        match self.overflowing_add(other) {
+            (sum, false) => sum,
+            (_, true) => U256::max_value(),
+        }
+    }
+
+    /// Multiply by a u64, returning whether the result overflowed.
+    pub fn overflowing_mul_u64(&self, other: u64) -> (U256, bool) {
+        let mut out = [0u64; 4];
+        let mut carry: u128 = 0;
+        for i in 0..4 {
+            let product = self.0[i] as u128 * other as u128 + carry;
+            out[i] = product as u64;
+            carry = product >> 64;
+        }
+        (U256(out), carry != 0)
+    }
+
+    /// Long division, returns `(quotient, remainder)`. Panics when dividing by zero.
+    pub fn div_rem(&self, divisor: &U256) -> (U256, U256) {
+        assert!(!divisor.is_zero(), "U256 division by zero");
+        let mut quotient = U256::zero();
+        let mut remainder = U256::zero();
+        for i in (0..256).rev() {
+            let (shifted, overflow) = remainder.shl1();
+            remainder = shifted;
+            if self.bit(i) {
+                remainder.0[0] |= 1;
+            }
+            // 左移溢出时真实余数 >= 2^256 > divisor，减法的回绕正好得到正确结果
+            if overflow || remainder >= *divisor {
+                remainder = remainder.overflowing_sub(divisor).0;
+                quotient.set_bit(i);
+            }
+        }
+        (quotient, remainder)
+    }
+}
+
+impl std::ops::Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &U256) -> std::cmp::Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                std::cmp::Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &U256) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::convert::From<u64> for U256 {
+    fn from(input: u64) -> U256 {
+        U256([input, 0, 0, 0])
+    }
+}
+
+impl std::convert::From<&H256> for U256 {
+    fn from(input: &H256) -> U256 {
+        // H256 is big endian
+        let bytes: [u8; 32] = input.into();
+        let mut limbs = [0u64; 4];
+        for i in 0..4 {
+            let mut limb = [0u8; 8];
+            limb.copy_from_slice(&bytes[(3 - i) * 8..(4 - i) * 8]);
+            limbs[i] = u64::from_be_bytes(limb);
+        }
+        U256(limbs)
+    }
+}
+
+impl std::convert::From<&U256> for H256 {
+    fn from(input: &U256) -> H256 {
+        let mut bytes = [0u8; 32];
+        for i in 0..4 {
+            bytes[(3 - i) * 8..(4 - i) * 8].copy_from_slice(&input.0[i].to_be_bytes());
+        }
+        bytes.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h256_roundtrip() {
+        let hash: H256 = (hex!("0005000000000000000000000000000000000000000000000000000000000102")).into();
+        let num = U256::from(&hash);
+        assert_eq!(H256::from(&num), hash);
+        assert!(num > U256::from(0x0102));
+    }
+
+    #[test]
+    fn div_rem() {
+        let (q, r) = U256::max_value().div_rem(&U256::from(1 << 32));
+        assert_eq!(r, U256::from(u32::MAX as u64));
+        assert_eq!(q, U256([u64::MAX, u64::MAX, u64::MAX, u32::MAX as u64]));
+        let (q, r) = U256::from(100).div_rem(&U256::max_value());
+        assert_eq!(q, U256::zero());
+        assert_eq!(r, U256::from(100));
+    }
+
+    #[test]
+    fn mul_u64() {
+        let (p, overflow) = U256([u64::MAX, 1, 0, 0]).overflowing_mul_u64(4);
+        assert_eq!(p, U256([u64::MAX - 3, 7, 0, 0]));
+        assert!(!overflow);
+        assert!(U256::max_value().overflowing_mul_u64(2).1);
+    }
+}
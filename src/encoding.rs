@@ -0,0 +1,230 @@
+use crate::block::Header;
+use crate::crypto::hash::{H160, H256};
+use crate::script::{Op, Script};
+use crate::transaction::*;
+
+/// Version byte in front of every canonical encoding. Bump it whenever the layout below changes.
+pub const ENCODING_VERSION: u8 = 5;
+
+/// An object with a canonical byte encoding, used for both hashing and signing.
+///
+/// Integers are fixed width little endian, hashes and keys are their raw bytes, vectors are a
+/// u32 length followed by the items, and structs are their fields in declaration order.
+pub trait Encodable {
+    /// Append the encoding of the object to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Version byte followed by the encoding of the object.
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![ENCODING_VERSION];
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+impl Encodable for u8 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self);
+    }
+}
+
+impl Encodable for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encodable for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encodable for i64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encodable for [u8; 32] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+}
+
+impl Encodable for H256 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Encodable for H160 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_ref());
+    }
+}
+
+impl<T: Encodable> Encodable for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for item in self.iter() {
+            item.encode(buf);
+        }
+    }
+}
+
+/// An op is one tag byte, followed by the pushed bytes for `Push`
+impl Encodable for Op {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let tag: u8 = match self {
+            Op::Push(_) => 0x00,
+            Op::Dup => 0x01,
+            Op::Drop => 0x02,
+            Op::Swap => 0x03,
+            Op::Sha256 => 0x10,
+            Op::Hash160 => 0x11,
+            Op::Equal => 0x20,
+            Op::EqualVerify => 0x21,
+            Op::Verify => 0x22,
+            Op::CheckSig => 0x30,
+            Op::CheckSigVerify => 0x31,
+            Op::CheckMultiSig => 0x32,
+            Op::CheckMultiSigVerify => 0x33,
+            Op::CheckHeightVerify => 0x40,
+            Op::CheckTimeVerify => 0x41,
+        };
+        tag.encode(buf);
+        if let Op::Push(item) = self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl Encodable for Script {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+    }
+}
+
+impl Encodable for Header {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.parent_pointer.encode(buf);
+        self.nonce.encode(buf);
+        self.difficulty.encode(buf);
+        self.timestamp.encode(buf);
+        self.merkle_root.encode(buf);
+    }
+}
+
+impl Encodable for TxInput {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.prev_tx.encode(buf);
+        self.index.encode(buf);
+        self.unlock.encode(buf);
+        self.sequence.encode(buf);
+    }
+}
+
+impl Encodable for TxOutput {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.value.encode(buf);
+        self.lock.encode(buf);
+    }
+}
+
+impl Encodable for Transaction {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.inputs.encode(buf);
+        self.outputs.encode(buf);
+        self.lock_time.encode(buf);
+    }
+}
+
+impl Encodable for TxSignature {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.pk.encode(buf);
+        self.signature1.encode(buf);
+        self.signature2.encode(buf);
+    }
+}
+
+impl Encodable for SignedTransaction {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.tx.encode(buf);
+        self.signatures.encode(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::Hashable;
+
+    fn sample_transaction() -> Transaction {
+        Transaction {
+            inputs: vec![TxInput { prev_tx: [0x11; 32].into(), index: 2, unlock: Script(vec![Op::Push(vec![0x77, 0x88])]), sequence: SEQUENCE_FINAL }],
+            outputs: vec![
+                TxOutput { value: 0x01020304, lock: Script::p2pkh(&[0x22; 20].into()) },
+                TxOutput { value: 5, lock: Script::p2pkh(&[0x66; 20].into()) },
+            ],
+            lock_time: 0x0a0b0c0d,
+        }
+    }
+
+    #[test]
+    fn transaction_vector() {
+        let tx = sample_transaction();
+        assert_eq!(
+            tx.canonical_bytes(),
+            hex!("05
+                  01000000 1111111111111111111111111111111111111111111111111111111111111111 02000000
+                           01000000 00 02000000 7788 ffffffff
+                  02000000 04030201 05000000 01 11 00 14000000 2222222222222222222222222222222222222222 21 30
+                           05000000 05000000 01 11 00 14000000 6666666666666666666666666666666666666666 21 30
+                  0d0c0b0a00000000").to_vec()
+        );
+        assert_eq!(
+            tx.hash(),
+            (hex!("4104a9c80932f39f441f1da1490fd72af45632a4b5249175001e26ab960bbb55")).into()
+        );
+    }
+
+    #[test]
+    fn signed_transaction_vector() {
+        let signed = SignedTransaction {
+            tx: sample_transaction(),
+            signatures: vec![TxSignature { pk: [0x33; 32], signature1: [0x44; 32], signature2: [0x55; 32] }],
+        };
+        let mut expected = sample_transaction().canonical_bytes();
+        expected.extend_from_slice(&hex!("01000000"));
+        expected.extend_from_slice(&[0x33; 32]);
+        expected.extend_from_slice(&[0x44; 32]);
+        expected.extend_from_slice(&[0x55; 32]);
+        assert_eq!(signed.canonical_bytes(), expected);
+    }
+
+    #[test]
+    fn header_vector() {
+        let header = Header {
+            parent_pointer: [0xaa; 32].into(),
+            nonce: 7,
+            difficulty: [0xbb; 32].into(),
+            timestamp: -2,
+            merkle_root: [0xcc; 32].into(),
+        };
+        assert_eq!(
+            header.canonical_bytes(),
+            hex!("05
+                  aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa 07000000
+                  bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb feffffffffffffff
+                  cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc").to_vec()
+        );
+    }
+
+    #[test]
+    fn vec_prefix() {
+        let mut buf = Vec::new();
+        vec![1u32, 2u32].encode(&mut buf);
+        assert_eq!(buf, hex!("02000000 01000000 02000000").to_vec());
+    }
+}
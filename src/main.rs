@@ -6,9 +6,16 @@ pub mod api;
 pub mod block;
 pub mod blockchain;
 pub mod crypto;
+pub mod encoding;
+pub mod mempool;
 pub mod miner;
 pub mod network;
+pub mod orphan;
+pub mod params;
+pub mod script;
+pub mod store;
 pub mod transaction;
+pub mod utxo;
 
 use clap::clap_app;
 use crossbeam::channel;
@@ -41,6 +48,9 @@ fn main() {
      (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg chain: --chain [SPEC] default_value("main") "Sets the network to run, either a built-in name (main, test) or a chain spec JSON file")
+     (@arg datadir: --datadir [DIR] "Sets the directory where blocks are stored across restarts, one subdirectory per network")
+     (@arg block_interval: --("block-interval") [MS] "Overrides the target block interval in milliseconds of the chain spec")
     )
     .get_matches();
 
@@ -81,10 +91,42 @@ fn main() {
     //added
     
 
-    let mut new_blockchain = Blockchain::new();
+    // 读取链参数
+    let chain_spec = matches.value_of("chain").unwrap();
+    let mut chain_params = params::ChainParams::load(chain_spec).unwrap_or_else(|e| {
+        error!("Error loading chain spec {}: {}", chain_spec, e);
+        process::exit(1);
+    });
+    if let Some(block_interval) = matches.value_of("block_interval") {
+        chain_params.block_interval = match block_interval.parse::<i64>() {
+            Ok(interval) if interval > 0 => interval,
+            Ok(interval) => {
+                error!("Block interval must be positive, got {}", interval);
+                process::exit(1);
+            }
+            Err(e) => {
+                error!("Error parsing block interval: {}", e);
+                process::exit(1);
+            }
+        };
+    }
+    info!("Running network {}", chain_params.name);
+
+    let mut new_orphanchain = orphan::OrphanPool::new(chain_params.max_orphan_blocks, chain_params.orphan_expire_time);
+    let orphan_txs = orphan::OrphanTxPool::new(chain_params.max_orphan_txs, chain_params.orphan_expire_time);
+    let mut new_blockchain = Blockchain::with_params(chain_params);
+    if let Some(datadir) = matches.value_of("datadir") {
+        // 不同网络的块分开存
+        let datadir = std::path::Path::new(datadir).join(&new_blockchain.params.name);
+        new_blockchain.open_store(&datadir).unwrap_or_else(|e| {
+            error!("Error opening block store in {}: {}", datadir.display(), e);
+            process::exit(1);
+        });
+        info!("Loaded {} blocks from {}, tip {:?}", new_blockchain.chain.len(), datadir.display(), new_blockchain.tip());
+    }
     let mut new_blockchain_arc = Arc::new(Mutex::new(new_blockchain));
-    let mut new_orphanchain : HashMap<H256,Block> = HashMap::new();
     let mut new_orphanchain_arc = Arc::new(Mutex::new(new_orphanchain));
+    let orphan_txs_arc = Arc::new(Mutex::new(orphan_txs));
 
     
 
@@ -104,6 +146,7 @@ fn main() {
         &server,
         &Arc::clone(&new_blockchain_arc),
         &Arc::clone(&new_orphanchain_arc),
+        &Arc::clone(&orphan_txs_arc),
     );
     worker_ctx.start();
 
@@ -153,6 +196,7 @@ fn main() {
         api_addr,
         &miner,
         &server,
+        &Arc::clone(&new_blockchain_arc),
     );
 
     loop {
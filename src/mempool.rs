@@ -0,0 +1,564 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::crypto::hash::H256;
+use crate::transaction::{SignedTransaction, TxValidationError, signals_rbf};
+use crate::utxo::OutPoint;
+
+pub const MAX_REPLACED_TXS: usize = 100;    // 一次替换最多移出多少笔tx（含后代）
+pub const MAX_ANCESTORS: usize = 25;        // 池中祖先加自己最多多少笔
+pub const MAX_DESCENDANTS: usize = 25;      // 池中后代加自己最多多少笔
+
+/// A transaction waiting to be mined, with what the pool orders it by
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolEntry {
+    pub tx: SignedTransaction,
+    pub fee: u64,
+    pub size: usize,    // 序列化后的字节数
+    pub time: i64,      // 进入mempool的时间（毫秒）
+}
+
+impl MempoolEntry {
+    pub fn new(tx: SignedTransaction, fee: u64, time: i64) -> Self {
+        let size = bincode::serialize(&tx).unwrap().len();
+        MempoolEntry {
+            tx: tx,
+            fee: fee,
+            size: size,
+            time: time,
+        }
+    }
+
+    /// Fee per 1000 bytes
+    pub fn fee_rate(&self) -> u64 {
+        fee_rate(self.fee, self.size)
+    }
+}
+
+pub fn fee_rate(fee: u64, size: usize) -> u64 {
+    fee.saturating_mul(1000) / (size.max(1) as u64)
+}
+
+/// A pool transaction together with all its pool ancestors
+#[derive(Eq, PartialEq, Debug, Default, Clone, Copy)]
+pub struct AncestorPackage {
+    pub fee: u64,
+    pub size: usize,
+    pub count: usize,   // 含自己
+}
+
+impl AncestorPackage {
+    pub fn fee_rate(&self) -> u64 {
+        fee_rate(self.fee, self.size)
+    }
+
+    fn add(&mut self, entry: &MempoolEntry) {
+        self.fee += entry.fee;
+        self.size += entry.size;
+        self.count += 1;
+    }
+
+    fn subtract(&mut self, entry: &MempoolEntry) {
+        self.fee -= entry.fee;
+        self.size -= entry.size;
+        self.count -= 1;
+    }
+}
+
+/// Reasons a transaction does not get into the mempool
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum MempoolError {
+    AlreadyInPool,
+    Invalid(TxValidationError),
+    MissingParents(Vec<H256>),      // 花的output还没见过，可以先当orphan等这些父tx
+    Conflict { outpoint: OutPoint, spender: H256 },     // 池中已有tx花了同一个outpoint，且它不可替换
+    ReplacementFeeTooLow { fee: u64, replaced_fee: u64, fee_rate: u64, replaced_fee_rate: u64 },
+    TooManyReplacements(usize),
+    SpendsReplaced(H256),       // 花了自己要替换掉的tx的output
+    TooManyAncestors(usize),
+    TooManyDescendants(H256),   // 这个祖先的后代已达上限
+    PoolFull { fee_rate: u64, min_fee_rate: u64 },  // 费率不高于要挤掉的tx，放不进
+}
+
+/// Unconfirmed transactions keyed by txid.
+/// Entries are also indexed by fee rate, by arrival time and by the outpoints they spend. The pool
+/// holds at most `max_bytes` of transactions and makes room by evicting the lowest fee rate.
+/// A transaction spending the same outpoint as a pool entry is rejected, unless that entry opts in
+/// to replace-by-fee and the new transaction pays more, see `insert`.
+/// Entries may spend outputs of other entries; such chains are limited to `MAX_ANCESTORS` and
+/// `MAX_DESCENDANTS` and are mined together as packages, see `packages_by_ancestor_fee_rate`.
+#[derive(Eq, PartialEq, Debug, Default, Clone)]
+pub struct Mempool {
+    entries: HashMap<H256, MempoolEntry>,
+    by_fee_rate: BTreeSet<(u64, Reverse<i64>, H256)>,  // 费率相同时后到的先被挤掉
+    by_time: BTreeSet<(i64, H256)>,
+    spent: HashMap<OutPoint, H256>,     // outpoint -> 花它的tx
+    ancestor_packages: HashMap<H256, AncestorPackage>,  // 随insert/remove更新
+    total_bytes: usize,
+    max_bytes: usize,
+}
+
+impl Mempool {
+    pub fn new(max_bytes: usize) -> Self {
+        Mempool {
+            max_bytes: max_bytes,
+            ..Mempool::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn contains(&self, txid: &H256) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &H256) -> Option<&SignedTransaction> {
+        self.entries.get(txid).map(|entry| &entry.tx)
+    }
+
+    pub fn entry(&self, txid: &H256) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    pub fn txids(&self) -> Vec<H256> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Fee, size and count of the pool transaction `txid` with its pool ancestors
+    pub fn ancestor_package(&self, txid: &H256) -> Option<AncestorPackage> {
+        self.ancestor_packages.get(txid).cloned()
+    }
+
+    fn compute_ancestor_package(&self, txid: &H256) -> AncestorPackage {
+        let entry = self.entries.get(txid).unwrap();
+        let mut package = AncestorPackage::default();
+        package.add(entry);
+        for ancestor in self.ancestors(&entry.tx) {
+            package.add(self.entries.get(&ancestor).unwrap());
+        }
+        package
+    }
+
+    /// The pool transaction spending `outpoint`
+    pub fn spender(&self, outpoint: &OutPoint) -> Option<&H256> {
+        self.spent.get(outpoint)
+    }
+
+    /// The outpoints `t` spends that pool transactions spend too, with those transactions
+    pub fn conflicts(&self, t: &SignedTransaction) -> Vec<(OutPoint, H256)> {
+        t.tx.inputs.iter()
+            .map(OutPoint::from)
+            .filter_map(|outpoint| self.spent.get(&outpoint).map(|spender| (outpoint, *spender)))
+            .collect()
+    }
+
+    /// Pool transactions whose outputs `t` spends, directly or through other pool transactions
+    pub fn ancestors(&self, t: &SignedTransaction) -> Vec<H256> {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![t];
+        while let Some(child) = queue.pop() {
+            for input in child.tx.inputs.iter() {
+                if let Some(parent) = self.entries.get(&input.prev_tx) {
+                    if seen.insert(input.prev_tx) {
+                        found.push(input.prev_tx);
+                        queue.push(&parent.tx);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Pool transactions spending outputs of `txid`, directly or through other pool transactions
+    pub fn descendants(&self, txid: &H256) -> Vec<H256> {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![*txid];
+        while let Some(parent) = queue.pop() {
+            let outputs = match self.entries.get(&parent) {
+                Some(entry) => entry.tx.tx.outputs.len(),
+                None => continue,
+            };
+            for index in 0..outputs {
+                if let Some(child) = self.spent.get(&OutPoint::new(parent, index as u32)) {
+                    if seen.insert(*child) {
+                        found.push(*child);
+                        queue.push(*child);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Pool transactions `entry` would replace: the ones it conflicts with and their descendants.
+    /// Every conflicting transaction has to signal replace-by-fee, and `entry` has to pay a higher
+    /// fee than all replaced transactions together and a higher fee rate than each conflict.
+    fn replaced_by(&self, entry: &MempoolEntry) -> Result<Vec<H256>, MempoolError> {
+        let mut replaced = Vec::new();
+        let mut replaced_fee: u64 = 0;
+        let mut replaced_fee_rate: u64 = 0;
+        for (outpoint, spender) in self.conflicts(&entry.tx) {
+            if replaced.contains(&spender) {
+                continue;
+            }
+            let other = self.entries.get(&spender).unwrap();
+            if !signals_rbf(&other.tx.tx) {
+                return Err(MempoolError::Conflict { outpoint: outpoint, spender: spender });
+            }
+            replaced_fee_rate = replaced_fee_rate.max(other.fee_rate());
+            replaced.push(spender);
+            replaced.extend(self.descendants(&spender));
+        }
+        replaced.sort();
+        replaced.dedup();
+        if replaced.len() > MAX_REPLACED_TXS {
+            return Err(MempoolError::TooManyReplacements(replaced.len()));
+        }
+        if let Some(input) = entry.tx.tx.inputs.iter().find(|input| replaced.contains(&input.prev_tx)) {
+            return Err(MempoolError::SpendsReplaced(input.prev_tx));
+        }
+        for txid in replaced.iter() {
+            replaced_fee += self.entries.get(txid).unwrap().fee;
+        }
+        if !replaced.is_empty() && (entry.fee <= replaced_fee || entry.fee_rate() <= replaced_fee_rate) {
+            return Err(MempoolError::ReplacementFeeTooLow {
+                fee: entry.fee,
+                replaced_fee: replaced_fee,
+                fee_rate: entry.fee_rate(),
+                replaced_fee_rate: replaced_fee_rate,
+            });
+        }
+        Ok(replaced)
+    }
+
+    /// Add an entry, replacing the pool transactions it conflicts with as allowed by `replaced_by`,
+    /// and evicting lower fee rate entries with their descendants if the pool would go over its
+    /// byte limit. Returns the txids of the replaced and evicted entries.
+    pub fn insert(&mut self, entry: MempoolEntry) -> Result<Vec<H256>, MempoolError> {
+        let txid = entry.tx.txid();
+        if self.contains(&txid) {
+            return Err(MempoolError::AlreadyInPool);
+        }
+        let ancestors = self.ancestors(&entry.tx);
+        if ancestors.len() + 1 > MAX_ANCESTORS {
+            return Err(MempoolError::TooManyAncestors(ancestors.len() + 1));
+        }
+        for ancestor in ancestors.iter() {
+            // 祖先自己、已有后代，再加上新tx
+            if self.descendants(ancestor).len() + 2 > MAX_DESCENDANTS {
+                return Err(MempoolError::TooManyDescendants(*ancestor));
+            }
+        }
+        let mut evict = self.replaced_by(&entry)?;
+        let mut freed: usize = evict.iter().map(|other| self.entries.get(other).unwrap().size).sum();
+        // 再算要挤掉哪些，都比新tx费率低才挤；不能挤掉新tx依赖的祖先
+        let rate = entry.fee_rate();
+        for (other_rate, _, other) in self.by_fee_rate.iter() {
+            if self.total_bytes - freed + entry.size <= self.max_bytes {
+                break;
+            }
+            if evict.contains(other) || ancestors.contains(other) {
+                continue;
+            }
+            if *other_rate >= rate {
+                return Err(MempoolError::PoolFull { fee_rate: rate, min_fee_rate: *other_rate });
+            }
+            for evicted in Some(*other).into_iter().chain(self.descendants(other)) {
+                if !evict.contains(&evicted) {
+                    freed += self.entries.get(&evicted).unwrap().size;
+                    evict.push(evicted);
+                }
+            }
+        }
+        if self.total_bytes - freed + entry.size > self.max_bytes {
+            return Err(MempoolError::PoolFull { fee_rate: rate, min_fee_rate: rate });
+        }
+        for other in evict.iter() {
+            self.remove(other);
+        }
+
+        for input in entry.tx.tx.inputs.iter() {
+            self.spent.insert(OutPoint::from(input), txid);
+        }
+        self.by_fee_rate.insert((rate, Reverse(entry.time), txid));
+        self.by_time.insert((entry.time, txid));
+        self.total_bytes += entry.size;
+        self.entries.insert(txid, entry);
+        // 一般没有后代；父tx晚于子tx进池时（如快照恢复），子tx的package重算
+        for other in Some(txid).into_iter().chain(self.descendants(&txid)) {
+            let package = self.compute_ancestor_package(&other);
+            self.ancestor_packages.insert(other, package);
+        }
+        Ok(evict)
+    }
+
+    pub fn remove(&mut self, txid: &H256) -> Option<MempoolEntry> {
+        if !self.contains(txid) {
+            return None;
+        }
+        // 后代的祖先package里去掉它
+        for other in self.descendants(txid) {
+            let entry = self.entries.get(txid).unwrap();
+            self.ancestor_packages.get_mut(&other).unwrap().subtract(entry);
+        }
+        self.ancestor_packages.remove(txid);
+        let entry = self.entries.remove(txid).unwrap();
+        for input in entry.tx.tx.inputs.iter() {
+            let outpoint = OutPoint::from(input);
+            if self.spent.get(&outpoint) == Some(txid) {
+                self.spent.remove(&outpoint);
+            }
+        }
+        self.by_fee_rate.remove(&(entry.fee_rate(), Reverse(entry.time), *txid));
+        self.by_time.remove(&(entry.time, *txid));
+        self.total_bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Remove a transaction along with its descendants, which can no longer be mined without it.
+    /// Returns the txids removed.
+    pub fn remove_with_descendants(&mut self, txid: &H256) -> Vec<H256> {
+        if !self.contains(txid) {
+            return Vec::new();
+        }
+        let mut removed = vec![*txid];
+        removed.extend(self.descendants(txid));
+        for other in removed.iter() {
+            self.remove(other);
+        }
+        removed
+    }
+
+    /// Remove a transaction that got into a block, along with pool transactions spending any of the
+    /// same outpoints and their descendants, which can no longer be mined. Returns the txids removed
+    /// as conflicts.
+    pub fn remove_confirmed(&mut self, t: &SignedTransaction) -> Vec<H256> {
+        let txid = t.txid();
+        self.remove(&txid);
+        let mut conflicts = Vec::new();
+        for (_, other) in self.conflicts(t) {
+            conflicts.push(other);
+            conflicts.extend(self.descendants(&other));
+        }
+        for other in conflicts.iter() {
+            self.remove(other);
+        }
+        conflicts
+    }
+
+    /// Drop entries that arrived before `cutoff` (milliseconds) and their descendants, returning
+    /// their txids
+    pub fn expire(&mut self, cutoff: i64) -> Vec<H256> {
+        let expired: Vec<H256> = self.by_time.iter()
+            .take_while(|(time, _)| *time < cutoff)
+            .map(|(_, txid)| *txid)
+            .collect();
+        let mut removed = Vec::new();
+        for txid in expired.iter() {
+            removed.extend(self.remove_with_descendants(txid));
+        }
+        removed
+    }
+
+    /// Entries from the highest fee rate to the lowest, earlier arrivals first on equal fee rate
+    pub fn iter_by_fee_rate(&self) -> impl Iterator<Item = &MempoolEntry> + '_ {
+        self.by_fee_rate.iter().rev().map(move |(_, _, txid)| self.entries.get(txid).unwrap())
+    }
+
+    /// Txids grouped into packages for block assembly. Each round picks the transaction whose not
+    /// yet picked pool ancestors together with it have the highest fee rate, so a child paying a
+    /// high fee gets its low fee parents mined. A package lists ancestors before descendants.
+    pub fn packages_by_ancestor_fee_rate(&self) -> Vec<Vec<H256>> {
+        // 未选的tx按祖先package费率排序；选中一个package后只更新其中各tx的后代
+        let mut remaining = self.ancestor_packages.clone();
+        let key = |txid: &H256, package: &AncestorPackage| (package.fee_rate(), Reverse(self.entries[txid].time), *txid);
+        let mut order: BTreeSet<(u64, Reverse<i64>, H256)> = remaining.iter().map(|(txid, package)| key(txid, package)).collect();
+        let mut packages = Vec::new();
+        while let Some(best) = order.iter().next_back().cloned() {
+            let txid = best.2;
+            let mut package: Vec<H256> = self.ancestors(&self.entries[&txid].tx).into_iter()
+                .filter(|ancestor| remaining.contains_key(ancestor))
+                .collect();
+            package.push(txid);
+            // 祖先数比它的任何祖先都多，按祖先数排序即父在子前
+            package.sort_by_key(|id| self.ancestor_packages[id].count);
+            for picked in package.iter() {
+                let picked_package = remaining.remove(picked).unwrap();
+                order.remove(&key(picked, &picked_package));
+                let entry = &self.entries[picked];
+                for other in self.descendants(picked) {
+                    if let Some(other_package) = remaining.get_mut(&other) {
+                        order.remove(&key(&other, other_package));
+                        other_package.subtract(entry);
+                        order.insert(key(&other, other_package));
+                    }
+                }
+            }
+            packages.push(package);
+        }
+        packages
+    }
+
+    /// All entries, in no particular order
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> + '_ {
+        self.entries.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::transaction::{generate_signed_txs, TxInput, SEQUENCE_FINAL, SEQUENCE_RBF_MAX};
+
+    fn entry(fee: u64, time: i64) -> MempoolEntry {
+        MempoolEntry::new(generate_signed_txs(), fee, time)
+    }
+
+    /// An entry spending the first output of `parent`
+    fn child_of(parent: &MempoolEntry, fee: u64, time: i64) -> MempoolEntry {
+        let mut t = generate_signed_txs();
+        t.tx.inputs = vec![TxInput{prev_tx: parent.tx.txid(), index: 0, unlock: Script::default(), sequence: SEQUENCE_FINAL}];
+        MempoolEntry::new(t, fee, time)
+    }
+
+    #[test]
+    fn orders_by_fee_rate() {
+        let mut pool = Mempool::new(1000000);
+        let low = entry(10, 0);
+        let high = entry(500, 2);
+        let high_later = entry(500, 3);
+        for e in vec![high_later.clone(), low.clone(), high.clone()] {
+            pool.insert(e).unwrap();
+        }
+        assert_eq!(pool.insert(low.clone()), Err(MempoolError::AlreadyInPool));
+        let order: Vec<H256> = pool.iter_by_fee_rate().map(|e| e.tx.txid()).collect();
+        assert_eq!(order, vec![high.tx.txid(), high_later.tx.txid(), low.tx.txid()]);
+        assert_eq!(pool.spender(&OutPoint::from(&low.tx.tx.inputs[0])), Some(&low.tx.txid()));
+
+        // 花同一个outpoint的另一笔tx
+        let mut double = generate_signed_txs();
+        double.tx.inputs = low.tx.tx.inputs.clone();
+        let outpoint = OutPoint::from(&low.tx.tx.inputs[0]);
+        assert_eq!(pool.insert(MempoolEntry::new(double, 1000, 4)), Err(MempoolError::Conflict { outpoint: outpoint, spender: low.tx.txid() }));
+
+        assert_eq!(pool.expire(3), vec![low.tx.txid(), high.tx.txid()]);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.total_bytes(), high_later.size);
+        assert!(pool.spender(&OutPoint::from(&low.tx.tx.inputs[0])).is_none());
+    }
+
+    #[test]
+    fn evicts_lowest_fee_rate() {
+        let first = entry(100, 0);
+        let size = first.size;
+        let mut pool = Mempool::new(size * 2);
+        let second = entry(300, 1);
+        pool.insert(first.clone()).unwrap();
+        pool.insert(second.clone()).unwrap();
+
+        // 费率不比最低的高，进不来
+        let cheap = entry(100, 2);
+        assert_eq!(pool.insert(cheap), Err(MempoolError::PoolFull { fee_rate: first.fee_rate(), min_fee_rate: first.fee_rate() }));
+        let better = entry(200, 3);
+        assert_eq!(pool.insert(better.clone()), Ok(vec![first.tx.txid()]));
+        assert!(pool.contains(&better.tx.txid()) && pool.contains(&second.tx.txid()));
+        assert_eq!(pool.total_bytes(), size * 2);
+    }
+
+    #[test]
+    fn child_pays_for_parent() {
+        let mut pool = Mempool::new(1000000);
+        let parent = entry(1, 0);
+        let other = entry(200, 1);
+        let child = child_of(&parent, 1000, 2);
+        for e in vec![parent.clone(), other.clone(), child.clone()] {
+            pool.insert(e).unwrap();
+        }
+        assert_eq!(pool.ancestors(&child.tx), vec![parent.tx.txid()]);
+        assert_eq!(pool.descendants(&parent.tx.txid()), vec![child.tx.txid()]);
+        // 单看费率parent最低，和child一起打包就排在other前面
+        assert_eq!(pool.packages_by_ancestor_fee_rate(), vec![
+            vec![parent.tx.txid(), child.tx.txid()],
+            vec![other.tx.txid()],
+        ]);
+
+        assert_eq!(pool.ancestor_package(&child.tx.txid()), Some(AncestorPackage { fee: 1001, size: parent.size + child.size, count: 2 }));
+
+        // parent确认后，child的package只剩自己
+        let mut confirmed = pool.clone();
+        confirmed.remove_confirmed(&parent.tx);
+        assert_eq!(confirmed.ancestor_package(&child.tx.txid()), Some(AncestorPackage { fee: 1000, size: child.size, count: 1 }));
+        assert_eq!(confirmed.packages_by_ancestor_fee_rate(), vec![vec![child.tx.txid()], vec![other.tx.txid()]]);
+
+        assert_eq!(pool.remove_with_descendants(&parent.tx.txid()), vec![parent.tx.txid(), child.tx.txid()]);
+        assert_eq!(pool.txids(), vec![other.tx.txid()]);
+        assert_eq!(pool.total_bytes(), other.size);
+    }
+
+    #[test]
+    fn limits_ancestors() {
+        let mut pool = Mempool::new(1000000);
+        let mut last = entry(10, 0);
+        pool.insert(last.clone()).unwrap();
+        for time in 1..MAX_ANCESTORS as i64 {
+            let next = child_of(&last, 10, time);
+            pool.insert(next.clone()).unwrap();
+            last = next;
+        }
+        assert_eq!(pool.insert(child_of(&last, 10, 100)), Err(MempoolError::TooManyAncestors(MAX_ANCESTORS + 1)));
+        assert_eq!(pool.len(), MAX_ANCESTORS);
+        assert_eq!(pool.ancestor_package(&last.tx.txid()).unwrap().count, MAX_ANCESTORS);
+    }
+
+    #[test]
+    fn replace_by_fee() {
+        let mut pool = Mempool::new(1000000);
+        let mut original = generate_signed_txs();
+        original.tx.inputs[0].sequence = SEQUENCE_RBF_MAX;
+        let original = MempoolEntry::new(original, 100, 0);
+        pool.insert(original.clone()).unwrap();
+
+        let spend_same = |fee: u64, time: i64| {
+            let mut t = generate_signed_txs();
+            t.tx.inputs = original.tx.tx.inputs.clone();
+            MempoolEntry::new(t, fee, time)
+        };
+        // 手续费没有严格更高，不能替换
+        let same_fee = spend_same(100, 1);
+        match pool.insert(same_fee) {
+            Err(MempoolError::ReplacementFeeTooLow { replaced_fee: 100, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let replacement = spend_same(100000, 2);
+        assert_eq!(pool.insert(replacement.clone()), Ok(vec![original.tx.txid()]));
+        assert!(!pool.contains(&original.tx.txid()));
+        assert_eq!(pool.spender(&OutPoint::from(&original.tx.tx.inputs[0])), Some(&replacement.tx.txid()));
+        assert_eq!(pool.total_bytes(), replacement.size);
+
+        // 没有发出可替换信号的tx，手续费再高也不能替换
+        let mut final_tx = generate_signed_txs();
+        final_tx.tx.inputs[0].sequence = SEQUENCE_FINAL;
+        let final_entry = MempoolEntry::new(final_tx, 100, 3);
+        pool.insert(final_entry.clone()).unwrap();
+        let mut again = generate_signed_txs();
+        again.tx.inputs = final_entry.tx.tx.inputs.clone();
+        match pool.insert(MempoolEntry::new(again, 100000, 4)) {
+            Err(MempoolError::Conflict { spender, .. }) => assert_eq!(spender, final_entry.tx.txid()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
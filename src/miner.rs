@@ -3,6 +3,7 @@ use crate::network::server::Handle as ServerHandle;
 use crate::blockchain::*;
 use crate::block::*;
 use crate::transaction::*;
+use crate::utxo::{OutPoint, UtxoSet};
 use std::sync::Arc;
 use std::sync::Mutex;
 use rand::Rng;
@@ -133,11 +134,11 @@ impl Context {
         let mut init_state_hash = Vec::new();
         for i in 0..2 {
             let rand_txhash:[u8;32] = rng.gen();
-            let rd_txhash:H256 = rand_txhash.into();
+            let rd_txhash = OutPoint::new(rand_txhash.into(), 0);
             init_state_hash.push(rd_txhash.clone());
             let chain = Arc::clone(&self.block_chain);
             let mut parentchain = chain.lock().unwrap();
-            parentchain.ledger_state.insert(rd_txhash, generate_state(&keypair));
+            parentchain.ledger_state.add(rd_txhash, generate_state(&keypair));
         }
 
         // 同步初始state
@@ -145,6 +146,7 @@ impl Context {
         self.server.broadcast(Message::NewStateHashes(init_state_hash.clone())); 
 
         println!("state: {:?}", init_state_hash);
+        let params = self.block_chain.lock().unwrap().params.clone();
         let mut try_time = 0;
         // main mining loop
         loop {
@@ -156,7 +158,7 @@ impl Context {
                 let parentchain = chain.lock().unwrap();
 
                 let mut state_before_mining = Vec::new();
-                for i in parentchain.ledger_state.keys() {
+                for i in parentchain.ledger_state.outpoints() {
                     state_before_mining.push(i.clone());
                 }
                 //info!("Synchronizing Ledger State.");
@@ -166,20 +168,16 @@ impl Context {
             // check duration
             let end_time = Local::now().timestamp();
             let gene_txs_flag = try_time % 3000;  
-            let duration = 120; 
+            let duration = params.run_duration; 
             if end_time - start_time >= duration {
                 let chain = Arc::clone(&self.block_chain);
                 let parentchain = chain.lock().unwrap();
                 info!("Time's done. Total {:?} seconds.", duration);
-                println!("Final Longest chain: {:?}",parentchain.longest_chain);
-                let mut memp = Vec::new();
+                println!("Final Longest chain: {:?}",parentchain.all_blocks_in_longest_chain());
+                let memp = parentchain.mempool.txids();
                 let mut state = Vec::new();
 
-                for i in parentchain.tx_mempool.keys() {
-                    memp.push(i);
-                }
-
-                for j in parentchain.ledger_state.keys() {
+                for j in parentchain.ledger_state.outpoints() {
                     state.push(j);
                 }
 
@@ -222,49 +220,41 @@ impl Context {
                 let mut emp_vec = Vec::new();
                 let mut rand_tx = generate_signed_txs();
                 let ad_tx = rng.gen_range(0,5);
-                let mut state = HashMap::new();
+                let mut state = UtxoSet::new();
+                let mut height = 0;
+                let time;
                 if ad_tx != 4 {
                     let chain = Arc::clone(&self.block_chain);
                     let mut parentchain = chain.lock().unwrap();
                     let state_now = parentchain.ledger_state.clone();
                     let new_tx = generate_confirmed_signedtransaction(&state_now, &keypair);
-                    parentchain.tx_mempool.insert(new_tx.clone().hash(), new_tx.clone());
+                    if let Err(e) = parentchain.add_to_mempool(new_tx.clone(), Local::now().timestamp_millis()) {
+                        debug!("Generated tx not added to mempool: {:?}", e);
+                    }
                     state = parentchain.ledger_state.clone();
+                    height = parentchain.longest_height + 1;
+                    time = parentchain.median_time_past(&parentchain.tip);
                     rand_tx = new_tx.clone();
-                    emp_vec.push(new_tx.clone().hash());
-                    info!("New Confirmed Tx Generated! Hash: {:?}", new_tx.clone().hash());
-                    let pool_clone = parentchain.tx_mempool.clone();
-                    for j in pool_clone.keys() {
-                        let tx = parentchain.tx_mempool.get(j).unwrap().clone();
-                        if check_tx(&tx, &parentchain.ledger_state) {
-                            continue;
-                        } else {
-                            parentchain.tx_mempool.remove(j);
-                        }
-                    }
-                    println!("TX pool size: {:?}", parentchain.tx_mempool.keys().len());
+                    emp_vec.push(new_tx.txid());
+                    info!("New Confirmed Tx Generated! Hash: {:?}", new_tx.txid());
+                    parentchain.expire_mempool(Local::now().timestamp_millis());
+                    println!("TX pool size: {:?}", parentchain.mempool.len());
                 } else {
                     let chain = Arc::clone(&self.block_chain);
                     let mut parentchain = chain.lock().unwrap();
                     let state_now = parentchain.ledger_state.clone();
                     let new_tx = generate_signed_txs();
-                    //parentchain.tx_mempool.insert(new_tx.clone().hash(), new_tx.clone());
+                    //parentchain.add_to_mempool(new_tx.clone(), Local::now().timestamp_millis());
                     state = parentchain.ledger_state.clone();
+                    height = parentchain.longest_height + 1;
+                    time = parentchain.median_time_past(&parentchain.tip);
                     rand_tx = new_tx.clone();
-                    emp_vec.push(new_tx.clone().hash());
-                    warn!("New Adversary Tx Generated! Hash: {:?}", new_tx.clone().hash());
-                    let pool_clone = parentchain.tx_mempool.clone();
-                    for j in pool_clone.keys() {
-                        let tx = parentchain.tx_mempool.get(j).unwrap().clone();
-                        if check_tx(&tx, &parentchain.ledger_state) {
-                            continue;
-                        } else {
-                            parentchain.tx_mempool.remove(j);
-                        }
-                    }
-                    println!("TX pool size: {:?}", parentchain.tx_mempool.keys().len());
+                    emp_vec.push(new_tx.txid());
+                    warn!("New Adversary Tx Generated! Hash: {:?}", new_tx.txid());
+                    parentchain.expire_mempool(Local::now().timestamp_millis());
+                    println!("TX pool size: {:?}", parentchain.mempool.len());
                 }
-                if check_tx(&rand_tx, &state) {
+                if check_tx(&rand_tx, &state, height, time, params.coinbase_maturity).is_ok() {
                     self.server.broadcast(Message::NewTransactionHashes(emp_vec)); 
                 }
                 
@@ -275,23 +265,24 @@ impl Context {
             let mut parentchain = chain.lock().unwrap();
 
             let parentblock = parentchain.chain.get(&parentchain.tip).unwrap(); // 找链上最长块
-            let parentdiff = parentblock.header.difficulty; //difficulty
+            let parentdiff = parentchain.next_difficulty(&parentchain.tip()); //difficulty
             let parentheight = parentblock.content.height;  //height
+            let parenttime = parentchain.median_time_past(&parentchain.tip);   //脚本里的时间锁按这个算
   
             // set timestamp
             let time_mill = Local::now().timestamp_millis();
 
             //------------------------------------------
-            // bonus for miner
-            let bonus_tx_for_miner = bonus_tx(&miner_key);
+            // bonus for miner，先占位，选完txs后加上手续费重新生成
+            let bonus_tx_for_miner = bonus_tx(&miner_key, params.block_subsidy(parentheight + 1), parentheight + 1);
 
             // ---------------------------------------------------
 
             // set default transac
             let mut transac:Vec<H256> = Vec::new();
             let mut transac_dt: HashMap<H256, SignedTransaction> = HashMap::new();
-            transac.push(bonus_tx_for_miner.hash());
-            transac_dt.insert(bonus_tx_for_miner.hash(), bonus_tx_for_miner);
+            transac.push(bonus_tx_for_miner.txid());
+            transac_dt.insert(bonus_tx_for_miner.txid(), bonus_tx_for_miner);
 
             // content
             let content = Content{
@@ -314,89 +305,99 @@ impl Context {
                 content: content,
             };
 
-            let size:usize = 700;
             // 检查条件，插入块
 
             // 从mempool读一定数量txs，插入
 
             // loop, until max size
             
-            let transactionmmp = parentchain.tx_mempool.clone();// mempool备份
+            // 按祖先package费率从高到低，子tx可以带上低费率的父tx
+            let packages = parentchain.mempool.packages_by_ancestor_fee_rate();
             // 动态state
             let mut check_state = parentchain.ledger_state.clone();
-            let mut check_mmp = transactionmmp.clone();
-
-            for key in transactionmmp.keys() {
-                // 循环插入txs
-                let tx_waited = transactionmmp.get(key).unwrap();   //拿到SignedTransaction
-
-                if !check_tx(tx_waited, &parentchain.ledger_state) {
-                    parentchain.tx_mempool.remove(key);
-                    check_mmp.remove(key);
-                } 
-
-                if check_tx(tx_waited, &check_state) { //如果当前tx合法，选中假定删除state
-                    mined_block.content.transaction_detail.insert(tx_waited.clone().hash(),tx_waited.clone());
-                    mined_block.content.transactions.push(tx_waited.clone().hash());
-                    check_state.remove(&tx_waited.tx.input.prev_tx);
-                    check_mmp.remove(key);
+            let mut fees: u64 = 0;
+
+            for package in packages.iter() {
+                // 整个package一起放进块，或者都不放
+                let mut package_state = check_state.clone();
+                let mut package_fees: u64 = 0;
+                let mut package_txs: Vec<SignedTransaction> = Vec::new();
+                for txid in package.iter() {
+                    let tx_waited = match parentchain.mempool.get(txid) {
+                        Some(t) => t.clone(),
+                        None => break,      // 前面作为无效tx的后代被移除了
+                    };
+                    if parentchain.check_mempool_tx(&tx_waited).is_err() {
+                        parentchain.mempool.remove_with_descendants(txid);
+                        break;
+                    }
+                    //如果当前tx合法，选中假定删除state；父tx因块大小没放进来时不合法，留在mempool
+                    if check_tx(&tx_waited, &package_state, parentheight + 1, parenttime, params.coinbase_maturity).is_err() {
+                        break;
+                    }
+                    package_fees += tx_fee(&tx_waited, &package_state).unwrap();
+                    package_state.apply_tx(&tx_waited, parentheight + 1, parenttime);
+                    package_txs.push(tx_waited);
+                }
+                if package_txs.len() < package.len() {
+                    continue;
+                }
+                for tx_waited in package_txs.iter() {
+                    mined_block.content.transaction_detail.insert(tx_waited.txid(),tx_waited.clone());
+                    mined_block.content.transactions.push(tx_waited.txid());
                 }
 
-                let new_merkleroot = MerkleTree::new(&(mined_block.content.transactions)).root();
-                mined_block.header.merkle_root = new_merkleroot;
-
-                //calc size now
-                let size_now = bincode::serialize(&mined_block).unwrap();
-                //println!("{:?}",size_now.len());
-                let size_long = size_now.len();
-
-                if size_long > size {
-                    //println!("{:?}",mined_block.content.transactions.len());
-                    break;
+                //calc size now，超过上限就撤回这个package，后面更小的可能还放得下
+                let size_long = bincode::serialize(&mined_block).unwrap().len();
+                if size_long > params.max_block_size {
+                    for tx_waited in package_txs.iter() {
+                        mined_block.content.transactions.pop();
+                        mined_block.content.transaction_detail.remove(&tx_waited.txid());
+                    }
+                    continue;
                 }
+                fees += package_fees;
+                check_state = package_state;
             }
+            // coinbase领取奖励加手续费，value长度固定，块大小不变
+            let claim = (params.block_subsidy(parentheight + 1) as u64 + fees).min(u32::max_value() as u64) as u32;
+            let bonus_tx_for_miner = bonus_tx(&miner_key, claim, parentheight + 1);
+            let placeholder = mined_block.content.transactions[0];
+            mined_block.content.transaction_detail.remove(&placeholder);
+            mined_block.content.transactions[0] = bonus_tx_for_miner.txid();
+            mined_block.content.transaction_detail.insert(bonus_tx_for_miner.txid(), bonus_tx_for_miner);
+            mined_block.header.merkle_root = mined_block.content.merkle_root();
 
-            let mut delete_state:Vec<H256> = Vec::new();
+            let mut delete_state:Vec<OutPoint> = Vec::new();
 
             if mined_block.hash() <= mined_block.header.difficulty {
                 
                 // 更新state
                 
-                for i in parentchain.ledger_state.keys() {
-                    if check_state.contains_key(i) {
+                for i in parentchain.ledger_state.outpoints() {
+                    if check_state.contains(i) {
                         continue;
                     } else {
                         delete_state.push(i.clone());
                     }
                 }
 
-                parentchain.ledger_state = check_state; // 删除花了的
-                for key in mined_block.content.transaction_detail.keys() {  //添加新生成的
-                    let new_state = mined_block.content.transaction_detail.get(key).unwrap().clone();
-                    if new_state.tx.input.prev_tx != [0;32].into() {
-                        parentchain.ledger_state.insert(key.clone(),new_state.tx.output);
-                    }   
-                }
-                // 更新mmp
-                for mmptx in parentchain.tx_mempool.clone().keys() {
-                    let tx_update = parentchain.tx_mempool.get(mmptx).unwrap();
-                    if check_tx(tx_update, &parentchain.ledger_state) {
-                        continue;
-                    } else {
-                        parentchain.tx_mempool.remove(mmptx);
-                    }
-                }
-                // 符合条件，加txs，插入
+                // 符合条件，加txs，插入（insert负责更新state）
                 println!("\n");
                 println!("///////////////===============================================");
                 info!("new block mined!, tip: {:?}, longest height: {:?}. parent: {:?}, hash: {:?}. Time: {:?}", parentchain.tip, parentchain.longest_height, mined_block.header.parent_pointer, mined_block.header.hash(), mined_block.header.timestamp);
                 
-                parentchain.insert(&mined_block);
+                if let Err(e) = parentchain.insert(&mined_block) {
+                    warn!("Mined block rejected by own chain: {:?}", e);
+                }
                 println!("===============================================///////////////");
                 println!("\n");
 
+                // 更新mmp
+                parentchain.expire_mempool(Local::now().timestamp_millis());
+
                 //println!("block's tx: {:?}", mined_block.content.transaction_detail);
-                //println!(" Tx mempool(txs' hash): {:?} \n Ledger state: {:?}",  parentchain.tx_mempool.keys(),parentchain.ledger_state);
+                //println!(" Tx mempool(txs' hash): {:?} \n Ledger state: {:?}",  parentchain.mempool.txids(),parentchain.ledger_state);
                 let mut braod_line:Vec<H256> = Vec::new();
                 braod_line.push(mined_block.hash()); 
                 //println!("broadcasting new blocks -- {:?}", braod_line);
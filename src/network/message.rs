@@ -2,6 +2,8 @@ use serde::{Serialize, Deserialize};
 use crate::crypto::hash::{H256};
 use crate::block::Block;
 use crate::transaction::*;
+use crate::utxo::{OutPoint, UtxoEntry};
+use crate::mempool::MempoolError;
 use std::collections::HashMap;
 
 
@@ -17,11 +19,12 @@ pub enum Message {
     NewTransactionHashes(Vec<H256>),
     GetTransactions(Vec<H256>),
     Transactions(Vec<SignedTransaction>),
+    RejectTransaction(H256, MempoolError),      // 对方发来的tx没进mempool及原因，如无效或冲突
 
-    NewStateHashes(Vec<H256>),
-    GetStates(Vec<H256>),
-    States(HashMap<H256,TxOutput>),
+    NewStateHashes(Vec<OutPoint>),
+    GetStates(Vec<OutPoint>),
+    States(HashMap<OutPoint,UtxoEntry>),
 
-    DeleteStateHashes(Vec<H256>),
+    DeleteStateHashes(Vec<OutPoint>),
     
 }
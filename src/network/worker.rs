@@ -7,6 +7,8 @@ use log::{debug, warn};
 use std::thread;
 
 use crate::blockchain::*;
+use crate::orphan::{OrphanPool, OrphanTxPool};
+use crate::mempool::MempoolError;
 use crate::block::*;
 use crate::crypto::hash::*;
 use std::sync::Arc;
@@ -23,7 +25,8 @@ pub struct Context {
     num_worker: usize,
     server: ServerHandle,
     block_chain: Arc<Mutex<Blockchain>>,        // added for blockchain's tip
-    orphan_chain: Arc<Mutex<HashMap<H256,Block>>>,   
+    orphan_chain: Arc<Mutex<OrphanPool>>,
+    orphan_txs: Arc<Mutex<OrphanTxPool>>,
 }
 
 pub fn new(
@@ -31,7 +34,8 @@ pub fn new(
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
     server: &ServerHandle,
     blockchain: &Arc<Mutex<Blockchain>>,
-    orphanchain: &Arc<Mutex<HashMap<H256, Block>>>,     //<parent_pointer, block>
+    orphanchain: &Arc<Mutex<OrphanPool>>,
+    orphantxs: &Arc<Mutex<OrphanTxPool>>,
 ) -> Context {
     Context {
         msg_chan: msg_src,
@@ -39,9 +43,32 @@ pub fn new(
         server: server.clone(),
         block_chain: Arc::clone(blockchain),
         orphan_chain: Arc::clone(orphanchain),
+        orphan_txs: Arc::clone(orphantxs),
     }
 }
 
+/// Retry the orphan transactions waiting for `parents`, and then those waiting for any of them
+/// that got into the mempool. Returns the txids that got in.
+fn promote_orphan_txs(chain: &mut Blockchain, orphan_txs: &mut OrphanTxPool, parents: Vec<H256>, now: i64) -> Vec<H256> {
+    let mut promoted = Vec::new();
+    let mut waiting = parents;
+    while let Some(parent) = waiting.pop() {
+        for orphan in orphan_txs.remove_children(&parent) {
+            let txid = orphan.txid();
+            match chain.add_to_mempool(orphan.clone(), now) {
+                Ok(_) => {
+                    promoted.push(txid);
+                    waiting.push(txid);
+                }
+                // 还缺别的父tx，放回去继续等
+                Err(MempoolError::MissingParents(missing)) => orphan_txs.insert(orphan, missing, now),
+                Err(e) => debug!("Dropped orphan tx {:?}: {:?}", txid, e),
+            }
+        }
+    }
+    promoted
+}
+
 impl Context {
     pub fn start(self) {
         println!("worker start");
@@ -128,61 +155,63 @@ impl Context {
                 Message::Blocks(blocks) => {
                     let mut parentchain = chain.lock().unwrap();       // 父链初始化
                     //debug!("received blocks ");
-                    let copy_chain = parentchain.clone();
                     let orphan = Arc::clone(&self.orphan_chain);
-                    let mut orphan_line = orphan.lock().unwrap();
+                    let mut orphan_pool = orphan.lock().unwrap();
+                    let mut orphan_txs = self.orphan_txs.lock().unwrap();
+                    let now = Local::now().timestamp_millis();
+                    orphan_pool.expire(now);
+                    orphan_txs.expire(now);
                     //debug!("Blocks");
                     // 收到消息后（收到是块信息），判断是否存在链中
                     let mut newblocks:Vec<H256> = Vec::new();
+                    let mut newtxids:Vec<H256> = Vec::new();      // 新块里的tx，可能是orphan tx的父tx
                     let mut missingparent:Vec<H256> = Vec::new();
                     for block in blocks.iter() {
-                        if copy_chain.chain.contains_key(&block.header.hash()) {    //如果链中有新到的块
+                        let hash = block.header.hash();
+                        if parentchain.chain.contains_key(&hash) || orphan_pool.contains(&hash) {    //如果链中或orphan中有新到的块
                             continue;   //不作处理
-                        } else if copy_chain.chain.contains_key(&block.header.parent_pointer) { //链中有块的父块
-                            // 验证difficulty
-                            if block.header.hash() <= copy_chain.chain.get(&block.header.parent_pointer).unwrap().header.difficulty {
-                                // 1. 插入新块
+                        } else if parentchain.chain.contains_key(&block.header.parent_pointer) { //链中有块的父块
+                            // 插入新块，再把等待它的orphan及其后代依次接上
+                            let mut waiting = vec![block.clone()];
+                            while let Some(next) = waiting.pop() {
+                                // 完整验证后再插入
+                                if let Err(e) = parentchain.validate_block(&next) {
+                                    warn!("Rejected block {:?}: {:?}", next.header.hash(), e);
+                                    continue;
+                                }
                                 println!("\n");
                                 println!("///////////////===============================================");
-                                debug!("New BLOCK received!, tip: {:?}, longest height: {:?}. parent: {:?}, hash: {:?}. Time: {:?}", parentchain.tip, parentchain.longest_height, block.header.parent_pointer, block.header.hash(),  block.header.timestamp);
-                                parentchain.insert(block);  //插入
+                                debug!("New BLOCK received!, tip: {:?}, longest height: {:?}. parent: {:?}, hash: {:?}. Time: {:?}", parentchain.tip, parentchain.longest_height, next.header.parent_pointer, next.header.hash(),  next.header.timestamp);
+                                let inserted = parentchain.insert(&next);  //插入
                                 println!("===============================================///////////////");
                                 println!("\n");
-                                newblocks.push(block.header.hash()); //入栈待广播队列
-                                
-                                // 2. 判断新块是不是某orphan的父块
-                                let mut start_block = block.clone();
-                                loop {
-                                    if orphan_line.contains_key(&start_block.header.hash()) { //是某一orphan的父块
-                                        let orphan_here = orphan_line.get(&start_block.header.hash()).unwrap();
-                                        println!("\n");
-                                        println!("///////////////===============================================");
-                                        println!("New BLOCK received!, tip: {:?}, longest height: {:?}. parent: {:?}, hash: {:?}. Time: {:?}", parentchain.tip, parentchain.longest_height, orphan_here.header.parent_pointer, orphan_here.header.hash(),  orphan_here.header.timestamp);
-                                        println!("===============================================///////////////");
-                                        println!("\n");
-                                        parentchain.insert(&orphan_here.clone());     //插入此orphan块
-                                        start_block = orphan_here.clone();          // 更新尝试父块
-
-                                        newblocks.push(orphan_here.header.hash()); //入栈待广播队列
-                                    } else {    // 新尝试块不是orphan中某块父块，跳出循环
-                                        break;
-                                    }
+                                if let Err(e) = inserted {
+                                    warn!("Rejected block {:?}: {:?}", next.header.hash(), e);
+                                    continue;
                                 }
+                                newblocks.push(next.header.hash()); //入栈待广播队列
+                                newtxids.extend(next.content.transactions.iter().cloned());
+                                // 判断新块是不是某些orphan的父块
+                                waiting.extend(orphan_pool.remove_children(&next.header.hash()));
                             }
                         } else {    // 链中没有新块且没有父块
-                            let blockcln = block.clone();
-                            orphan_line.insert(block.header.parent_pointer,blockcln);   //入orphan到缓存，key设为父块哈希值
-                            missingparent.push(block.header.parent_pointer);    // 入栈parent hash值
+                            // 父块未知没法完整验证，至少检查PoW，不让白给的块占满orphan缓存
+                            if hash > block.header.difficulty || block.header.difficulty > parentchain.params.genesis_difficulty {
+                                warn!("Rejected orphan block {:?}: hash above target", hash);
+                                continue;
+                            }
+                            orphan_pool.insert(block.clone(), now);   //入orphan到缓存
+                            // 父块可能也是orphan，请求最早缺失的祖先
+                            let missing = orphan_pool.missing_ancestor(&hash);
+                            if !missingparent.contains(&missing) {
+                                missingparent.push(missing);    // 入栈parent hash值
+                            }
                         }
                     }
-                    let pool_clone = parentchain.tx_mempool.clone();
-                    for j in pool_clone.keys() {
-                       let tx = parentchain.tx_mempool.get(j).unwrap().clone();
-                        if check_tx(&tx, &parentchain.ledger_state) {
-                            continue;
-                        } else {
-                            parentchain.tx_mempool.remove(j);
-                        }
+                    parentchain.expire_mempool(now);
+                    let promoted = promote_orphan_txs(&mut parentchain, &mut orphan_txs, newtxids, now);
+                    if promoted.len() > 0 {
+                        self.server.broadcast(Message::NewTransactionHashes(promoted));
                     }
 
                     if missingparent.len() > 0 {
@@ -200,14 +229,15 @@ impl Context {
                     
                     let parentchain = chain.lock().unwrap();       // 父链初始化
                     let copy_chain = parentchain.clone();
+                    let orphan_txs = self.orphan_txs.lock().unwrap();
                     //debug!("Heared new txs : {:?}", txhashes);
                     
                     let mut calling_tx = Vec::new();
-                    // 检查是否在mempool中，不在，请求
+                    // 检查是否在mempool或orphan tx中，不在，请求
 
                     for tx_hs in txhashes.iter() {
                         // 链中的mempool含有此hash
-                        if copy_chain.tx_mempool.contains_key(&tx_hs){
+                        if copy_chain.mempool.contains(&tx_hs) || orphan_txs.contains(&tx_hs) {
                             continue;
                         }
                         else {
@@ -228,9 +258,8 @@ impl Context {
                     let mut exist_tx = Vec::new();
 
                     for tx_hs in txhashes.iter() {
-                        if copy_chain.tx_mempool.contains_key(tx_hs) {
-                            let tx_now = copy_chain.tx_mempool.get(tx_hs).unwrap().clone();
-                            exist_tx.push(tx_now);
+                        if let Some(tx_now) = copy_chain.mempool.get(tx_hs) {
+                            exist_tx.push(tx_now.clone());
                         }
                     }
 
@@ -242,37 +271,56 @@ impl Context {
                     let mut parentchain = chain.lock().unwrap();       // 父链初始化
                     //debug!("Received txs");
                     let copy_chain = parentchain.clone();
+                    let mut orphan_txs = self.orphan_txs.lock().unwrap();
+                    let now = Local::now().timestamp_millis();
                     // 判断是否在缓存中，如果不在，check，并加入缓存
                     let mut new_tx = Vec::new();
-                    //let mut miss_tx = Vec::new();
+                    let mut miss_tx = Vec::new();
                     for i in txs.iter() {
-                        if copy_chain.tx_mempool.contains_key(&i.hash()) {
+                        if copy_chain.mempool.contains(&i.txid()) || orphan_txs.contains(&i.txid()) {
                             continue;
                         } else {
-                            if check_tx(i, &copy_chain.ledger_state.clone()) {
-                                parentchain.tx_mempool.insert(i.hash(), i.clone());   // 加入缓存
-                                debug!("New Confirmed Tx founded! Hash: {:?}", i.hash());
-                                new_tx.push(i.hash());
-                                
+                            match parentchain.add_to_mempool(i.clone(), now) {
+                                Ok(evicted) => {
+                                    // 替换了池中的tx也一样转发，让其他节点也换掉
+                                    debug!("New Confirmed Tx founded! Hash: {:?}, replaced or evicted {:?}", i.txid(), evicted);
+                                    new_tx.push(i.txid());
+                                    new_tx.extend(promote_orphan_txs(&mut parentchain, &mut orphan_txs, vec![i.txid()], now));
+                                }
+                                Err(MempoolError::AlreadyInPool) => {}
+                                Err(MempoolError::MissingParents(parents)) => {
+                                    // 先存为orphan，向发送方要缺的父tx
+                                    debug!("Orphan tx {:?}, missing parents {:?}", i.txid(), parents);
+                                    for parent in parents.iter() {
+                                        if !parentchain.mempool.contains(parent) && !miss_tx.contains(parent) {
+                                            miss_tx.push(*parent);
+                                        }
+                                    }
+                                    orphan_txs.insert(i.clone(), parents, now);
+                                }
+                                Err(e) => {
+                                    // 告诉发送方拒绝的原因，包括与池中tx冲突
+                                    parentchain.record_reject(i.txid(), e.clone());
+                                    peer.write(Message::RejectTransaction(i.txid(), e));
+                                }
                             }
                         }
                     }
 
-                    let pool_clone = parentchain.tx_mempool.clone();
-                    for j in pool_clone.keys() {
-                        let tx = parentchain.tx_mempool.get(j).unwrap().clone();
-                        if check_tx(&tx, &parentchain.ledger_state) {
-                            continue;
-                        } else {
-                            parentchain.tx_mempool.remove(j);
-                        }
-                    }
-                    println!("TX pool size: {:?}", parentchain.tx_mempool.keys().len());
+                    parentchain.expire_mempool(now);
+                    orphan_txs.expire(now);
+                    debug!("TX pool size: {:?}, orphan txs: {:?}", parentchain.mempool.len(), orphan_txs.len());
 
+                    if miss_tx.len() > 0 {
+                        peer.write(Message::GetTransactions(miss_tx));
+                    }
                     if new_tx.len()>0 {
                         self.server.broadcast(Message::NewTransactionHashes(new_tx)); 
                     }
                 }
+                Message::RejectTransaction(hash, e) => {
+                    warn!("Peer rejected transaction {:?}: {:?}", hash, e);
+                }
                 Message::NewStateHashes(statehashes) => {
                     
                     let parentchain = chain.lock().unwrap();       // 父链初始化
@@ -280,7 +328,7 @@ impl Context {
                     let copy_chain = parentchain.clone();
                     let mut new_state = Vec::new();
                     for i in statehashes.iter() {
-                        if copy_chain.ledger_state.contains_key(i) {
+                        if copy_chain.ledger_state.contains(i) {
                             continue;
                         } else {
                             new_state.push(i.clone());
@@ -301,8 +349,8 @@ impl Context {
 
                     let mut exist_state = HashMap::new();
                     for i in statehashes.iter() {
-                        if copy_chain.ledger_state.contains_key(i) {
-                            exist_state.insert(i.clone(), copy_chain.ledger_state.get(i).unwrap().clone());
+                        if copy_chain.ledger_state.contains(i) {
+                            exist_state.insert(i.clone(), copy_chain.ledger_state.entry(i).unwrap().clone());
                         }
                     }
                     if exist_state.clone().len() > 0 {
@@ -317,7 +365,7 @@ impl Context {
 
                     let mut new_state = Vec::new();
 
-                    for j in parentchain.ledger_state.keys() {
+                    for j in parentchain.ledger_state.outpoints() {
                         if states.contains_key(j) {
                             continue;
                         } else {
@@ -328,16 +376,16 @@ impl Context {
 
                     for i in states.keys() {
                         let state = states.get(i).unwrap();
-                        if copy_chain.ledger_state.contains_key(i) {
+                        if copy_chain.ledger_state.contains(i) {
                             continue;
                         } else {
                             new_state.push(i.clone());
-                            parentchain.ledger_state.insert(i.clone(), state.clone());
+                            parentchain.ledger_state.add_entry(i.clone(), state.clone());
                             //debug!("New state Tx Synchronized! Hash: {:?}", i);
                         }
                     }
                     let mut exist = Vec::new();
-                    for k in parentchain.ledger_state.keys() {
+                    for k in parentchain.ledger_state.outpoints() {
                         exist.push(k.clone());
                     }
                     println!("ledger_state now: {:?}", exist);
@@ -350,8 +398,8 @@ impl Context {
                     let mut parentchain = chain.lock().unwrap();       // 父链初始化
                     let copy_chain = parentchain.clone();
                     for i in state.iter() {
-                        if copy_chain.ledger_state.contains_key(i) {
-                            parentchain.ledger_state.remove(i);
+                        if copy_chain.ledger_state.contains(i) {
+                            parentchain.ledger_state.spend(i);
                             self.server.broadcast(Message::DeleteStateHashes(state.clone())); 
                         }
                     }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use crate::block::Block;
+use crate::crypto::hash::{H256, Hashable};
+use crate::transaction::SignedTransaction;
+
+/// Blocks whose parent is not in the chain yet. Every child of a missing parent is kept, and
+/// entries are evicted once the pool is full or they get too old.
+#[derive(Debug, Default, Clone)]
+pub struct OrphanPool {
+    blocks: HashMap<H256, (Block, i64)>,    // 块hash -> (块, 到达时间)
+    children: HashMap<H256, Vec<H256>>,     // 父块hash -> 子块hash
+    max_blocks: usize,
+    max_age: i64,
+}
+
+impl OrphanPool {
+    pub fn new(max_blocks: usize, max_age: i64) -> Self {
+        OrphanPool {
+            blocks: HashMap::new(),
+            children: HashMap::new(),
+            max_blocks: max_blocks,
+            max_age: max_age,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Add an orphan that arrived at `now` (milliseconds), evicting expired entries and then the
+    /// oldest ones while the pool is over its size limit
+    pub fn insert(&mut self, block: Block, now: i64) {
+        let hash = block.hash();
+        if self.contains(&hash) || self.max_blocks == 0 {
+            return;
+        }
+        self.expire(now);
+        while self.blocks.len() >= self.max_blocks {
+            let oldest = *self.blocks.iter().min_by_key(|(_, (_, time))| *time).unwrap().0;
+            self.remove(&oldest);
+        }
+        self.children.entry(block.header.parent_pointer).or_insert_with(Vec::new).push(hash);
+        self.blocks.insert(hash, (block, now));
+    }
+
+    fn remove(&mut self, hash: &H256) -> Option<Block> {
+        let (block, _) = self.blocks.remove(hash)?;
+        let parent = block.header.parent_pointer;
+        if let Some(siblings) = self.children.get_mut(&parent) {
+            siblings.retain(|h| h != hash);
+            if siblings.is_empty() {
+                self.children.remove(&parent);
+            }
+        }
+        Some(block)
+    }
+
+    /// Drop orphans that have waited longer than the age limit
+    pub fn expire(&mut self, now: i64) {
+        let max_age = self.max_age;
+        let expired: Vec<H256> = self.blocks.iter()
+            .filter(|(_, (_, time))| now - *time > max_age)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired.iter() {
+            self.remove(hash);
+        }
+    }
+
+    /// Take every orphan waiting for `parent` out of the pool
+    pub fn remove_children(&mut self, parent: &H256) -> Vec<Block> {
+        let hashes = self.children.remove(parent).unwrap_or_default();
+        hashes.iter().filter_map(|hash| self.blocks.remove(hash).map(|(block, _)| block)).collect()
+    }
+
+    /// The block that has to be fetched before this orphan can connect: walks up through
+    /// orphans that are themselves waiting for a parent
+    pub fn missing_ancestor(&self, hash: &H256) -> H256 {
+        let mut cursor = *hash;
+        while let Some((block, _)) = self.blocks.get(&cursor) {
+            cursor = block.header.parent_pointer;
+        }
+        cursor
+    }
+}
+
+/// Transactions spending outputs that are not in the ledger yet, kept until their parents show up.
+/// An orphan is indexed under every parent it waits for; like `OrphanPool`, entries are evicted
+/// once the pool is full or they get too old.
+#[derive(Debug, Default, Clone)]
+pub struct OrphanTxPool {
+    txs: HashMap<H256, (SignedTransaction, Vec<H256>, i64)>,   // txid -> (tx, 缺的父tx, 到达时间)
+    children: HashMap<H256, Vec<H256>>,     // 父txid -> 等它的orphan
+    max_txs: usize,
+    max_age: i64,
+}
+
+impl OrphanTxPool {
+    pub fn new(max_txs: usize, max_age: i64) -> Self {
+        OrphanTxPool {
+            txs: HashMap::new(),
+            children: HashMap::new(),
+            max_txs: max_txs,
+            max_age: max_age,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn contains(&self, txid: &H256) -> bool {
+        self.txs.contains_key(txid)
+    }
+
+    /// Add an orphan waiting for the transactions `parents`, arrived at `now` (milliseconds),
+    /// evicting expired entries and then the oldest ones while the pool is over its size limit
+    pub fn insert(&mut self, tx: SignedTransaction, parents: Vec<H256>, now: i64) {
+        let txid = tx.txid();
+        if self.contains(&txid) || self.max_txs == 0 {
+            return;
+        }
+        self.expire(now);
+        while self.txs.len() >= self.max_txs {
+            let oldest = *self.txs.iter().min_by_key(|(_, (_, _, time))| *time).unwrap().0;
+            self.remove(&oldest);
+        }
+        for parent in parents.iter() {
+            self.children.entry(*parent).or_insert_with(Vec::new).push(txid);
+        }
+        self.txs.insert(txid, (tx, parents, now));
+    }
+
+    fn remove(&mut self, txid: &H256) -> Option<SignedTransaction> {
+        let (tx, parents, _) = self.txs.remove(txid)?;
+        for parent in parents.iter() {
+            if let Some(siblings) = self.children.get_mut(parent) {
+                siblings.retain(|h| h != txid);
+                if siblings.is_empty() {
+                    self.children.remove(parent);
+                }
+            }
+        }
+        Some(tx)
+    }
+
+    /// Drop orphans that have waited longer than the age limit
+    pub fn expire(&mut self, now: i64) {
+        let max_age = self.max_age;
+        let expired: Vec<H256> = self.txs.iter()
+            .filter(|(_, (_, _, time))| now - *time > max_age)
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in expired.iter() {
+            self.remove(txid);
+        }
+    }
+
+    /// Take every orphan waiting for `parent` out of the pool, also from under its other parents.
+    /// Orphans still missing something else are expected to be inserted again.
+    pub fn remove_children(&mut self, parent: &H256) -> Vec<SignedTransaction> {
+        let txids = self.children.get(parent).cloned().unwrap_or_default();
+        txids.iter().filter_map(|txid| self.remove(txid)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+    use crate::transaction::generate_signed_txs;
+
+    #[test]
+    fn keeps_all_siblings() {
+        let mut pool = OrphanPool::new(10, 1000);
+        let parent: H256 = [1;32].into();
+        let a = generate_random_block(&parent);
+        let b = generate_random_block(&parent);
+        let grandchild = generate_random_block(&a.hash());
+        pool.insert(a.clone(), 0);
+        pool.insert(b.clone(), 0);
+        pool.insert(grandchild.clone(), 0);
+        assert_eq!(pool.missing_ancestor(&grandchild.hash()), parent);
+
+        let children = pool.remove_children(&parent);
+        assert_eq!(children.len(), 2);
+        assert!(children.contains(&a) && children.contains(&b));
+        assert_eq!(pool.remove_children(&a.hash()), vec![grandchild]);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn evicts_oldest_and_expired() {
+        let mut pool = OrphanPool::new(2, 1000);
+        let first = generate_random_block(&[1;32].into());
+        let second = generate_random_block(&[2;32].into());
+        let third = generate_random_block(&[3;32].into());
+        pool.insert(first.clone(), 0);
+        pool.insert(second.clone(), 10);
+        pool.insert(third.clone(), 20);
+        assert!(!pool.contains(&first.hash()));
+        assert_eq!(pool.len(), 2);
+
+        pool.expire(1015);
+        assert!(!pool.contains(&second.hash()));
+        assert!(pool.contains(&third.hash()));
+        assert!(pool.remove_children(&[2;32].into()).is_empty());
+    }
+
+    #[test]
+    fn orphan_txs_by_parent() {
+        let mut pool = OrphanTxPool::new(2, 1000);
+        let (p1, p2): (H256, H256) = ([1;32].into(), [2;32].into());
+        let both = generate_signed_txs();
+        let one = generate_signed_txs();
+        pool.insert(both.clone(), vec![p1, p2], 0);
+        pool.insert(one.clone(), vec![p2], 10);
+        assert_eq!(pool.len(), 2);
+
+        // 从p1取出后，p2下也不再有它
+        assert_eq!(pool.remove_children(&p1), vec![both.clone()]);
+        assert_eq!(pool.remove_children(&p2), vec![one.clone()]);
+        assert_eq!(pool.len(), 0);
+
+        pool.insert(both.clone(), vec![p1, p2], 0);
+        pool.insert(one.clone(), vec![p2], 10);
+        let third = generate_signed_txs();
+        pool.insert(third.clone(), vec![p1], 20);
+        assert!(!pool.contains(&both.txid()));
+        assert_eq!(pool.remove_children(&p1), vec![third]);
+        pool.expire(1015);
+        assert_eq!(pool.len(), 0);
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+
+use crate::crypto::hash::H256;
+
+/// Consensus and policy constants of one named network.
+/// Built-in networks are available by name, others can be loaded from a JSON chain spec file.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct ChainParams {
+    pub name: String,
+
+    // genesis块
+    pub genesis_nonce: u32,
+    #[serde(with = "hex_h256")]
+    pub genesis_difficulty: H256,   // 也是difficulty的上限
+    pub genesis_timestamp: i64,     // 毫秒，和区块时间戳一致
+
+    // 共识规则
+    pub initial_subsidy: u32,       // 矿工奖励，每halving_interval块减半
+    pub halving_interval: u32,      // 0表示不减半
+    pub coinbase_maturity: u32,     // coinbase的output要过多少块才能花
+    pub max_block_size: usize,      // 序列化后的块大小上限（字节）
+    pub block_interval: i64,        // 目标出块间隔（毫秒）
+    pub retarget_window: u32,       // 每隔多少块调整一次difficulty
+    pub median_time_span: usize,    // 取最近多少块的时间戳中位数
+    pub max_future_block_time: i64, // 块时间戳最多超前本地时间多少毫秒
+
+    // 本地策略
+    pub max_mempool_bytes: usize,
+    pub mempool_expiry: i64,        // tx在mempool中最多待多少毫秒
+    pub max_orphan_blocks: usize,
+    pub orphan_expire_time: i64,    // 毫秒，orphan块和orphan tx共用
+    pub max_orphan_txs: usize,
+    pub max_reorg_depth: u32,       // 最多回滚多少块，undo只保留这么多块
+    pub run_duration: i64,          // miner运行多少秒后结束
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams::main()
+    }
+}
+
+impl ChainParams {
+    /// The network all nodes ran before chain specs existed
+    pub fn main() -> Self {
+        ChainParams {
+            name: "main".to_string(),
+            genesis_nonce: 2083236893,
+            genesis_difficulty: [0,5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0].into(),
+            genesis_timestamp: 1231006505000,
+            initial_subsidy: 200000000,
+            halving_interval: 210000,
+            coinbase_maturity: 100,
+            max_block_size: 10000,
+            block_interval: 10000,
+            retarget_window: 10,
+            median_time_span: 11,
+            max_future_block_time: 2 * 60 * 60 * 1000,
+            max_mempool_bytes: 300000,
+            mempool_expiry: 60 * 60 * 1000,
+            max_orphan_blocks: 100,
+            orphan_expire_time: 10 * 60 * 1000,
+            max_orphan_txs: 100,
+            max_reorg_depth: 100,
+            run_duration: 120,
+        }
+    }
+
+    /// A local test network with easy, fast blocks
+    pub fn test() -> Self {
+        ChainParams {
+            name: "test".to_string(),
+            genesis_nonce: 0,
+            genesis_difficulty: [0,64,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0].into(),
+            genesis_timestamp: 1600000000000,
+            block_interval: 2000,
+            retarget_window: 5,
+            halving_interval: 100,
+            coinbase_maturity: 5,
+            run_duration: 60,
+            ..ChainParams::main()
+        }
+    }
+
+    /// Newly minted coins a coinbase at `height` may claim on top of the fees
+    pub fn block_subsidy(&self, height: u32) -> u32 {
+        if self.halving_interval == 0 {
+            return self.initial_subsidy;
+        }
+        let halvings = height / self.halving_interval;
+        if halvings >= 32 {
+            return 0;
+        }
+        self.initial_subsidy >> halvings
+    }
+
+    /// Total subsidy of all blocks up to and including `height`
+    pub fn issued_at_height(&self, height: u32) -> u64 {
+        // genesis没有coinbase
+        let height = height as u64;
+        let interval = self.halving_interval as u64;
+        let mut total: u64 = 0;
+        let mut start: u64 = 1;
+        while start <= height {
+            let subsidy = self.block_subsidy(start as u32) as u64;
+            if subsidy == 0 {
+                break;
+            }
+            // 同一个减半周期内奖励相同
+            let end = if interval == 0 {
+                height
+            } else {
+                height.min((start / interval + 1) * interval - 1)
+            };
+            total += subsidy * (end - start + 1);
+            start = end + 1;
+        }
+        total
+    }
+
+    /// Upper bound on the coins ever minted, `None` if the subsidy never halves
+    pub fn max_supply(&self) -> Option<u64> {
+        if self.halving_interval == 0 && self.initial_subsidy > 0 {
+            return None;
+        }
+        Some(self.issued_at_height(u32::max_value()))
+    }
+
+    /// Built-in network with this name
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "main" => Some(ChainParams::main()),
+            "test" => Some(ChainParams::test()),
+            _ => None,
+        }
+    }
+
+    /// Read a chain spec from a JSON file
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let params: ChainParams = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        params.check()?;
+        Ok(params)
+    }
+
+    /// Reject values the chain cannot run with, e.g. ones that would divide by zero or let no block through
+    pub fn check(&self) -> Result<(), String> {
+        if self.block_interval <= 0 {
+            return Err(format!("block_interval must be positive, got {}", self.block_interval));
+        }
+        if self.retarget_window == 0 {
+            return Err("retarget_window must be positive".to_string());
+        }
+        if self.median_time_span == 0 {
+            return Err("median_time_span must be positive".to_string());
+        }
+        if self.max_block_size == 0 {
+            return Err("max_block_size must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// A built-in network name, or else the path of a chain spec file
+    pub fn load(spec: &str) -> Result<Self, String> {
+        match ChainParams::named(spec) {
+            Some(params) => Ok(params),
+            None => ChainParams::from_file(Path::new(spec)),
+        }
+    }
+}
+
+/// H256 written as a hex string in chain spec files
+mod hex_h256 {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::crypto::hash::H256;
+
+    pub fn serialize<S: Serializer>(hash: &H256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hash.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<H256, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.len() != 64 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(serde::de::Error::custom("expected 64 hex characters"));
+        }
+        let mut bytes = [0u8; 32];
+        for i in 0..32 {
+            bytes[i] = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(serde::de::Error::custom)?;
+        }
+        Ok(bytes.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_roundtrip() {
+        let params = ChainParams::test();
+        let text = serde_json::to_string_pretty(&params).unwrap();
+        assert!(text.contains("\"genesis_difficulty\": \"0040000000000000000000000000000000000000000000000000000000000000\""));
+        let parsed: ChainParams = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, params);
+        assert!(ChainParams::load("no-such-network.json").is_err());
+    }
+
+    #[test]
+    fn spec_rejects_bad_hex() {
+        let text = serde_json::to_string(&ChainParams::test()).unwrap();
+        let good = "0040000000000000000000000000000000000000000000000000000000000000";
+        // 多字节字符凑够64字节，不能在字符中间切
+        let multibyte = format!("é{}", &good[2..]);
+        assert_eq!(multibyte.len(), 64);
+        let signed = format!("+f{}", &good[2..]);
+        for bad in [multibyte, signed].iter() {
+            let spec = text.replace(good, bad);
+            assert!(serde_json::from_str::<ChainParams>(&spec).is_err());
+        }
+    }
+
+    #[test]
+    fn spec_rejects_unusable_values() {
+        let dir = crate::store::tests::temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("spec.json");
+        let broken = vec![
+            ChainParams { block_interval: 0, ..ChainParams::test() },
+            ChainParams { block_interval: -2000, ..ChainParams::test() },
+            ChainParams { retarget_window: 0, ..ChainParams::test() },
+            ChainParams { median_time_span: 0, ..ChainParams::test() },
+            ChainParams { max_block_size: 0, ..ChainParams::test() },
+        ];
+        for params in broken {
+            std::fs::write(&path, serde_json::to_string(&params).unwrap()).unwrap();
+            assert!(ChainParams::load(path.to_str().unwrap()).is_err());
+        }
+        std::fs::write(&path, serde_json::to_string(&ChainParams::test()).unwrap()).unwrap();
+        assert_eq!(ChainParams::load(path.to_str().unwrap()), Ok(ChainParams::test()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn subsidy_schedule() {
+        let mut params = ChainParams::main();
+        params.initial_subsidy = 100;
+        params.halving_interval = 10;
+        assert_eq!(params.block_subsidy(9), 100);
+        assert_eq!(params.block_subsidy(10), 50);
+        assert_eq!(params.block_subsidy(25), 25);
+        assert_eq!(params.block_subsidy(70), 0);
+        // 高度1..=9是100，10..=11是50
+        assert_eq!(params.issued_at_height(11), 9 * 100 + 2 * 50);
+        // 100,50,25,12,6,3,1，各10块（第一个周期9块）
+        assert_eq!(params.max_supply(), Some(9 * 100 + 10 * (50 + 25 + 12 + 6 + 3 + 1)));
+
+        params.halving_interval = 0;
+        assert_eq!(params.block_subsidy(1000000), 100);
+        assert_eq!(params.max_supply(), None);
+    }
+}
@@ -0,0 +1,344 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+use crate::crypto::hash::{H160, H256, convert_to_h160};
+
+pub const MAX_SCRIPT_OPS: usize = 201;      // 解锁加锁定脚本一共最多执行的op数
+pub const MAX_STACK_SIZE: usize = 100;
+pub const MAX_PUSH_SIZE: usize = 520;       // 单个栈元素最多字节数
+pub const MAX_MULTISIG_KEYS: usize = 20;
+
+/// One instruction of a script
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Op {
+    Push(Vec<u8>),
+    Dup,
+    Drop,
+    Swap,
+    Sha256,
+    Hash160,            // 与地址相同：sha256后取后20字节
+    Equal,
+    EqualVerify,
+    Verify,
+    CheckSig,           // 弹出pk，tx带有该pk的有效签名则压入true
+    CheckSigVerify,
+    CheckMultiSig,      // 栈上为 m pk_1 .. pk_n n，至少m个不同的pk签了名则压入true
+    CheckMultiSigVerify,
+    CheckHeightVerify,  // 栈顶（不弹出）为最低块高度
+    CheckTimeVerify,    // 栈顶（不弹出）为最早时间（毫秒）
+}
+
+/// Locking script of an output, or unlocking script of an input.
+/// An input can spend an output if running the unlocking script and then the locking script on one
+/// stack leaves a true value on top.
+#[derive(Hash, Eq, PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Script(pub Vec<Op>);
+
+/// Reasons a script fails
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum ScriptError {
+    NotPushOnly,        // 解锁脚本只能push
+    TooManyOps,
+    PushTooLarge,
+    StackUnderflow,
+    StackOverflow,
+    VerifyFailed,
+    BadNumber,
+    BadMultiSig,        // n或m超出范围，或pk长度不对
+    HeightLocked { required: u64 },
+    TimeLocked { required: u64 },
+    FalseResult,
+}
+
+/// What a script may look at while it runs: who signed the spending transaction and the block it goes into
+pub struct ScriptContext {
+    pub signers: HashSet<[u8; 32]>,     // 签名已经验证过的pk
+    pub height: u32,
+    pub time: i64,      // 父块的median time past（毫秒）
+}
+
+impl Script {
+    /// Pay to the owner of the public key hashing to `address`
+    pub fn p2pkh(address: &H160) -> Self {
+        Script(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(address.as_ref().to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// Unlocking script spending a `p2pkh` output with the key `pk`
+    pub fn p2pkh_unlock(pk: &[u8; 32]) -> Self {
+        Script(vec![Op::Push(pk.to_vec())])
+    }
+
+    /// Spendable by a transaction signed by at least `m` of `pks`, with an empty unlocking script
+    pub fn multisig(m: u8, pks: &[[u8; 32]]) -> Self {
+        let mut ops = vec![Op::Push(vec![m])];
+        ops.extend(pks.iter().map(|pk| Op::Push(pk.to_vec())));
+        ops.push(Op::Push(vec![pks.len() as u8]));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    /// The address a `p2pkh` script pays to
+    pub fn p2pkh_address(&self) -> Option<H160> {
+        match &self.0[..] {
+            [Op::Dup, Op::Hash160, Op::Push(address), Op::EqualVerify, Op::CheckSig] if address.len() == 20 => {
+                let mut bytes = [0u8; 20];
+                bytes.copy_from_slice(address);
+                Some(bytes.into())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.0.iter().all(|op| match op {
+            Op::Push(_) => true,
+            _ => false,
+        })
+    }
+}
+
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|b| *b != 0)
+}
+
+fn from_bool(value: bool) -> Vec<u8> {
+    if value { vec![1] } else { vec![] }
+}
+
+/// Little endian unsigned number of at most 8 bytes
+fn to_number(item: &[u8]) -> Result<u64, ScriptError> {
+    if item.len() > 8 {
+        return Err(ScriptError::BadNumber);
+    }
+    let mut bytes = [0u8; 8];
+    bytes[..item.len()].copy_from_slice(item);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn sha256(item: &[u8]) -> H256 {
+    ring::digest::digest(&ring::digest::SHA256, item).into()
+}
+
+/// Run `unlock` and then `lock` on one stack.
+/// Both scripts together may execute at most `MAX_SCRIPT_OPS` ops, and the stack is limited to
+/// `MAX_STACK_SIZE` items of at most `MAX_PUSH_SIZE` bytes, so every script terminates quickly.
+pub fn verify_script(unlock: &Script, lock: &Script, ctx: &ScriptContext) -> Result<(), ScriptError> {
+    if !unlock.is_push_only() {
+        return Err(ScriptError::NotPushOnly);
+    }
+    if unlock.0.len() + lock.0.len() > MAX_SCRIPT_OPS {
+        return Err(ScriptError::TooManyOps);
+    }
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    for op in unlock.0.iter().chain(lock.0.iter()) {
+        execute(op, &mut stack, ctx)?;
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackOverflow);
+        }
+    }
+    match stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(ScriptError::FalseResult),
+    }
+}
+
+fn execute(op: &Op, stack: &mut Vec<Vec<u8>>, ctx: &ScriptContext) -> Result<(), ScriptError> {
+    match op {
+        Op::Push(item) => {
+            if item.len() > MAX_PUSH_SIZE {
+                return Err(ScriptError::PushTooLarge);
+            }
+            stack.push(item.clone());
+        }
+        Op::Dup => {
+            let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+            stack.push(top);
+        }
+        Op::Drop => {
+            stack.pop().ok_or(ScriptError::StackUnderflow)?;
+        }
+        Op::Swap => {
+            let len = stack.len();
+            if len < 2 {
+                return Err(ScriptError::StackUnderflow);
+            }
+            stack.swap(len - 1, len - 2);
+        }
+        Op::Sha256 => {
+            let item = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            stack.push(sha256(&item).as_ref().to_vec());
+        }
+        Op::Hash160 => {
+            let item = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            stack.push(convert_to_h160(sha256(&item)).as_ref().to_vec());
+        }
+        Op::Equal | Op::EqualVerify => {
+            let a = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            let b = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            if *op == Op::Equal {
+                stack.push(from_bool(a == b));
+            } else if a != b {
+                return Err(ScriptError::VerifyFailed);
+            }
+        }
+        Op::Verify => {
+            let item = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            if !is_true(&item) {
+                return Err(ScriptError::VerifyFailed);
+            }
+        }
+        Op::CheckSig | Op::CheckSigVerify => {
+            let pk = stack.pop().ok_or(ScriptError::StackUnderflow)?;
+            let valid = signed_by(ctx, &pk);
+            if *op == Op::CheckSig {
+                stack.push(from_bool(valid));
+            } else if !valid {
+                return Err(ScriptError::VerifyFailed);
+            }
+        }
+        Op::CheckMultiSig | Op::CheckMultiSigVerify => {
+            let n = to_number(&stack.pop().ok_or(ScriptError::StackUnderflow)?)? as usize;
+            if n > MAX_MULTISIG_KEYS {
+                return Err(ScriptError::BadMultiSig);
+            }
+            if stack.len() < n + 1 {
+                return Err(ScriptError::StackUnderflow);
+            }
+            let pks = stack.split_off(stack.len() - n);
+            let m = to_number(&stack.pop().unwrap())? as usize;
+            if m > n || pks.iter().any(|pk| pk.len() != 32) {
+                return Err(ScriptError::BadMultiSig);
+            }
+            // 同一个pk列了两次也只算一次
+            let signers: HashSet<&Vec<u8>> = pks.iter().filter(|pk| signed_by(ctx, pk)).collect();
+            let valid = signers.len() >= m;
+            if *op == Op::CheckMultiSig {
+                stack.push(from_bool(valid));
+            } else if !valid {
+                return Err(ScriptError::VerifyFailed);
+            }
+        }
+        Op::CheckHeightVerify => {
+            let required = to_number(stack.last().ok_or(ScriptError::StackUnderflow)?)?;
+            if (ctx.height as u64) < required {
+                return Err(ScriptError::HeightLocked { required: required });
+            }
+        }
+        Op::CheckTimeVerify => {
+            let required = to_number(stack.last().ok_or(ScriptError::StackUnderflow)?)?;
+            if ctx.time < 0 || (ctx.time as u64) < required {
+                return Err(ScriptError::TimeLocked { required: required });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the spending transaction carries a valid signature by `pk`
+fn signed_by(ctx: &ScriptContext, pk: &[u8]) -> bool {
+    pk.len() == 32 && ctx.signers.iter().any(|signer| signer[..] == pk[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_pair;
+    use crate::transaction::*;
+    use ring::signature::KeyPair;
+
+    // 和check_tx一样，只把验证通过的签名算进去
+    fn context(tx: &SignedTransaction, height: u32) -> ScriptContext {
+        let signers = tx.signatures.iter()
+            .filter(|s| verify_u8(&tx.tx, &s.pk, &s.signature1, &s.signature2))
+            .map(|s| s.pk)
+            .collect();
+        ScriptContext { signers: signers, height: height, time: 1000 }
+    }
+
+    #[test]
+    fn p2pkh() {
+        let tx = generate_signed_txs();
+        let pk = tx.signatures[0].pk;
+        let lock = Script::p2pkh(&pk_to_h160(&pk));
+        assert_eq!(lock.p2pkh_address(), Some(pk_to_h160(&pk)));
+        assert_eq!(verify_script(&Script::p2pkh_unlock(&pk), &lock, &context(&tx, 1)), Ok(()));
+
+        // 别人的pk对不上地址
+        let other = pk_to_u8(key_pair::random().public_key());
+        assert_eq!(verify_script(&Script::p2pkh_unlock(&other), &lock, &context(&tx, 1)), Err(ScriptError::VerifyFailed));
+        // 地址对上，但tx不是这个pk签的
+        let mut forged = tx.clone();
+        forged.signatures[0].pk = other;
+        let lock = Script::p2pkh(&pk_to_h160(&other));
+        assert_eq!(verify_script(&Script::p2pkh_unlock(&other), &lock, &context(&forged, 1)), Err(ScriptError::FalseResult));
+    }
+
+    #[test]
+    fn multisig() {
+        let keys: Vec<_> = (0..3).map(|_| key_pair::random()).collect();
+        let pks: Vec<[u8; 32]> = keys.iter().map(|key| pk_to_u8(key.public_key())).collect();
+        let lock = Script::multisig(2, &pks);
+        let t = generate_random_transaction();
+
+        let one = sign_tx(t.clone(), &[&keys[0]]);
+        assert_eq!(verify_script(&Script::default(), &lock, &context(&one, 1)), Err(ScriptError::FalseResult));
+        // 同一个key签两次不算两个
+        let twice = sign_tx(t.clone(), &[&keys[0], &keys[0]]);
+        assert_eq!(verify_script(&Script::default(), &lock, &context(&twice, 1)), Err(ScriptError::FalseResult));
+        let two = sign_tx(t.clone(), &[&keys[2], &keys[0]]);
+        assert_eq!(verify_script(&Script::default(), &lock, &context(&two, 1)), Ok(()));
+        // 外人的签名不算
+        let outsider = key_pair::random();
+        let mixed = sign_tx(t.clone(), &[&keys[1], &outsider]);
+        assert_eq!(verify_script(&Script::default(), &lock, &context(&mixed, 1)), Err(ScriptError::FalseResult));
+
+        assert_eq!(verify_script(&Script::default(), &Script::multisig(4, &pks), &context(&two, 1)), Err(ScriptError::BadMultiSig));
+    }
+
+    #[test]
+    fn hash_and_height_locks() {
+        let tx = generate_signed_txs();
+        let preimage = b"secret".to_vec();
+        let digest = ring::digest::digest(&ring::digest::SHA256, &preimage).as_ref().to_vec();
+        let lock = Script(vec![
+            Op::Push(vec![10]),
+            Op::CheckHeightVerify,
+            Op::Drop,
+            Op::Sha256,
+            Op::Push(digest),
+            Op::Equal,
+        ]);
+        let unlock = Script(vec![Op::Push(preimage)]);
+        assert_eq!(verify_script(&unlock, &lock, &context(&tx, 9)), Err(ScriptError::HeightLocked { required: 10 }));
+        assert_eq!(verify_script(&unlock, &lock, &context(&tx, 10)), Ok(()));
+        let wrong = Script(vec![Op::Push(b"guess".to_vec())]);
+        assert_eq!(verify_script(&wrong, &lock, &context(&tx, 10)), Err(ScriptError::FalseResult));
+
+        let time_lock = Script(vec![Op::Push(1001u64.to_le_bytes().to_vec()), Op::CheckTimeVerify]);
+        assert_eq!(verify_script(&Script::default(), &time_lock, &context(&tx, 10)), Err(ScriptError::TimeLocked { required: 1001 }));
+    }
+
+    #[test]
+    fn resource_limits() {
+        let tx = generate_signed_txs();
+        let not_push = Script(vec![Op::Push(vec![1]), Op::Dup]);
+        assert_eq!(verify_script(&not_push, &Script::default(), &context(&tx, 1)), Err(ScriptError::NotPushOnly));
+
+        let mut dups = vec![Op::Push(vec![1])];
+        dups.extend(std::iter::repeat(Op::Dup).take(MAX_STACK_SIZE));
+        assert_eq!(verify_script(&Script::default(), &Script(dups), &context(&tx, 1)), Err(ScriptError::StackOverflow));
+
+        let long = Script(std::iter::repeat(Op::Push(vec![1])).take(MAX_SCRIPT_OPS + 1).collect());
+        assert_eq!(verify_script(&Script::default(), &long, &context(&tx, 1)), Err(ScriptError::TooManyOps));
+
+        let big = Script(vec![Op::Push(vec![1; MAX_PUSH_SIZE + 1])]);
+        assert_eq!(verify_script(&big, &Script::default(), &context(&tx, 1)), Err(ScriptError::PushTooLarge));
+        assert_eq!(verify_script(&Script::default(), &Script(vec![Op::Drop]), &context(&tx, 1)), Err(ScriptError::StackUnderflow));
+    }
+}
@@ -0,0 +1,189 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::block::Block;
+use crate::crypto::hash::{H256, Hashable};
+
+const BLOCKS_FILE: &str = "blocks.dat";     // 长度前缀 + bincode块，只追加
+const INDEX_FILE: &str = "index.dat";       // 32字节hash + 8字节offset，只追加
+const TIP_FILE: &str = "tip";
+const SNAPSHOT_FILE: &str = "utxo.dat";
+
+/// Blocks kept on disk in a data directory, with an index from block hash to file offset.
+/// Files are reopened for every operation, so the store can be cloned along with `Blockchain`.
+#[derive(Eq, PartialEq, Debug, Default, Clone)]
+pub struct BlockStore {
+    dir: PathBuf,
+    pub index: HashMap<H256, u64>,
+}
+
+impl BlockStore {
+    /// Open the store in `dir`, creating the directory if needed and loading the block index
+    pub fn open(dir: &Path) -> io::Result<BlockStore> {
+        fs::create_dir_all(dir)?;
+        let mut store = BlockStore {
+            dir: dir.to_path_buf(),
+            index: HashMap::new(),
+        };
+        let blocks_len = match fs::metadata(store.path(BLOCKS_FILE)) {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+        if let Ok(mut file) = File::open(store.path(INDEX_FILE)) {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            // 最后一条可能没写完，忽略不完整的记录和超出块文件的offset
+            for record in bytes.chunks_exact(40) {
+                let hash: [u8; 32] = record[0..32].try_into().unwrap();
+                let offset = u64::from_le_bytes(record[32..40].try_into().unwrap());
+                if offset < blocks_len {
+                    store.index.insert(hash.into(), offset);
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    /// Append a block to the block file, skipping blocks already stored
+    pub fn put(&mut self, block: &Block) -> io::Result<()> {
+        let hash = block.hash();
+        if self.contains(&hash) {
+            return Ok(());
+        }
+        let bytes = bincode::serialize(block).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut blocks = OpenOptions::new().create(true).append(true).open(self.path(BLOCKS_FILE))?;
+        let offset = blocks.seek(SeekFrom::End(0))?;
+        blocks.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        blocks.write_all(&bytes)?;
+        blocks.sync_data()?;
+
+        let mut index = OpenOptions::new().create(true).append(true).open(self.path(INDEX_FILE))?;
+        let hash_bytes: [u8; 32] = hash.into();
+        index.write_all(&hash_bytes)?;
+        index.write_all(&offset.to_le_bytes())?;
+        self.index.insert(hash, offset);
+        Ok(())
+    }
+
+    fn read_at(file: &mut File, offset: u64) -> io::Result<Block> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0u8; 4];
+        file.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        file.read_exact(&mut bytes)?;
+        bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn get(&self, hash: &H256) -> io::Result<Option<Block>> {
+        match self.index.get(hash) {
+            Some(offset) => {
+                let mut file = File::open(self.path(BLOCKS_FILE))?;
+                Ok(Some(BlockStore::read_at(&mut file, *offset)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// All indexed blocks in the order they were written, so parents come before children
+    pub fn blocks(&self) -> io::Result<Vec<Block>> {
+        let mut offsets: Vec<u64> = self.index.values().cloned().collect();
+        offsets.sort();
+        let mut blocks = Vec::new();
+        if offsets.is_empty() {
+            return Ok(blocks);
+        }
+        let mut file = File::open(self.path(BLOCKS_FILE))?;
+        for offset in offsets {
+            match BlockStore::read_at(&mut file, offset) {
+                Ok(block) => blocks.push(block),
+                Err(_) => break,    // 写了一半的块
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Write `contents` to `name` through a temporary file, so a crash never leaves it half written
+    fn replace_file(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let tmp = self.path(&format!("{}.tmp", name));
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_data()?;
+        fs::rename(tmp, self.path(name))
+    }
+
+    pub fn set_tip(&self, tip: &H256) -> io::Result<()> {
+        self.replace_file(TIP_FILE, tip.as_ref())
+    }
+
+    pub fn tip(&self) -> io::Result<Option<H256>> {
+        match fs::read(self.path(TIP_FILE)) {
+            Ok(bytes) if bytes.len() == 32 => {
+                let hash: [u8; 32] = bytes[..].try_into().unwrap();
+                Ok(Some(hash.into()))
+            }
+            Ok(_) => Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_snapshot<T: Serialize>(&self, snapshot: &T) -> io::Result<()> {
+        let bytes = bincode::serialize(snapshot).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.replace_file(SNAPSHOT_FILE, &bytes)
+    }
+
+    pub fn load_snapshot<T: DeserializeOwned>(&self) -> io::Result<Option<T>> {
+        match fs::read(self.path(SNAPSHOT_FILE)) {
+            Ok(bytes) => match bincode::deserialize(&bytes) {
+                Ok(snapshot) => Ok(Some(snapshot)),
+                Err(_) => Ok(None),     // 损坏的快照当作没有，改为重放
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+    use rand::Rng;
+
+    /// A fresh, empty directory under the system temp dir
+    pub fn temp_dir() -> PathBuf {
+        let suffix: u64 = rand::thread_rng().gen();
+        std::env::temp_dir().join(format!("blockstore-test-{:x}", suffix))
+    }
+
+    #[test]
+    fn put_get_reopen() {
+        let dir = temp_dir();
+        let mut store = BlockStore::open(&dir).unwrap();
+        let first = generate_random_block(&H256::default());
+        let second = generate_random_block(&first.hash());
+        store.put(&first).unwrap();
+        store.put(&second).unwrap();
+        store.put(&first).unwrap();
+        store.set_tip(&second.hash()).unwrap();
+        assert_eq!(store.get(&second.hash()).unwrap(), Some(second.clone()));
+
+        let reopened = BlockStore::open(&dir).unwrap();
+        assert_eq!(reopened.blocks().unwrap(), vec![first, second.clone()]);
+        assert_eq!(reopened.tip().unwrap(), Some(second.hash()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
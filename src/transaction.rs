@@ -4,7 +4,10 @@ use ring::signature::{self, Ed25519KeyPair, Signature, KeyPair};
 use rand::Rng;
 use ring::rand::{SecureRandom, SystemRandom};
 use crate::crypto::key_pair;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::encoding::Encodable;
+use crate::utxo::{OutPoint, UtxoEntry, UtxoSet};
+use crate::script::{Script, ScriptContext, ScriptError, verify_script};
 
 
 
@@ -12,29 +15,59 @@ use std::collections::HashMap;
 pub struct TxInput {
     pub prev_tx: H256,      //用来找上一个tx的input和output
     pub index : u32,
+    pub unlock: Script,     // 解锁脚本，只能push，例如p2pkh的pk
+    pub sequence: u32,      // 相对时间锁，见sequence_locked
 }
 
 #[derive(Hash, Eq, PartialEq,Debug, Default,Clone, Serialize, Deserialize)]
 pub struct TxOutput {
     pub value : u32,
-    pub address: H160,
+    pub lock: Script,       // 锁定脚本，通常是Script::p2pkh(address)
     //pub receiver_pk: [u8;32],
 }
 
 #[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Transaction {
-    pub input : TxInput,
-    pub output : TxOutput,
+    pub inputs : Vec<TxInput>,
+    pub outputs : Vec<TxOutput>,
+    pub lock_time : u64,    // 0表示不锁，小于LOCKTIME_THRESHOLD是块高度，否则是毫秒时间戳
 }
 
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+pub const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;    // 置位则input没有相对时间锁
+pub const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;       // 置位按秒计，否则按块数计
+pub const SEQUENCE_MASK: u32 = 0xffff;
+pub const SEQUENCE_RBF_MAX: u32 = 0xffff_fffd;     // 有input的sequence不超过它，tx就可以被替换
+
+/// Signature of a transaction by one key, split in two halves
 #[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
-pub struct SignedTransaction {
-    pub tx : Transaction,
+pub struct TxSignature {
     pub pk : [u8;32],
     pub signature1 : [u8;32],
     pub signature2 : [u8;32],
 }
 
+/// A transaction with the signatures of every key its inputs need, e.g. several for a multisig output
+#[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct SignedTransaction {
+    pub tx : Transaction,
+    pub signatures : Vec<TxSignature>,
+}
+
+impl SignedTransaction {
+    /// Id of the transaction, over the unsigned `Transaction` only, so that re-encoding or adding
+    /// signatures does not change it. Mempool, blocks and outpoints refer to transactions by it.
+    pub fn txid(&self) -> H256 {
+        self.tx.hash()
+    }
+
+    /// Hash over the transaction together with its signatures
+    pub fn wtxid(&self) -> H256 {
+        self.hash()
+    }
+}
+
 #[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Default, Clone)]
 pub struct BinSignedTransaction {
     pub tx : Transaction,
@@ -51,7 +84,7 @@ pub struct State {
 
 /// Create digital signature of a transaction
 pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
-    key.sign(bincode::serialize(&t).unwrap().as_ref())
+    key.sign(&t.canonical_bytes())
     // unimplemented!() 
 }
 
@@ -60,10 +93,29 @@ pub fn verify(t: &Transaction, public_key: &<Ed25519KeyPair as KeyPair>::PublicK
     let peer_public_key_bytes = public_key.as_ref();//u8格式
     let peer_public_key =
     signature::UnparsedPublicKey::new(&signature::ED25519, peer_public_key_bytes);
-    peer_public_key.verify(bincode::serialize(&t).unwrap().as_ref(), signature.as_ref()).is_ok()
+    peer_public_key.verify(&t.canonical_bytes(), signature.as_ref()).is_ok()
     // unimplemented!()
 }
 
+/// Sign a transaction with `key` in the format carried by `SignedTransaction`
+pub fn tx_signature(t: &Transaction, key: &Ed25519KeyPair) -> TxSignature {
+    let (signature1, signature2) = sig_to_2_u8(&sign(t, key));
+    TxSignature {
+        pk: pk_to_u8(key.public_key()),
+        signature1: signature1,
+        signature2: signature2,
+    }
+}
+
+/// Sign a transaction with all of `keys`
+pub fn sign_tx(tx: Transaction, keys: &[&Ed25519KeyPair]) -> SignedTransaction {
+    let signatures = keys.iter().map(|key| tx_signature(&tx, key)).collect();
+    SignedTransaction {
+        tx: tx,
+        signatures: signatures,
+    }
+}
+
 pub fn verify_u8(t: &Transaction, public_key: &[u8;32], signature1: &[u8;32], signature2: &[u8;32]) -> bool {
     let peer_public_key =
     signature::UnparsedPublicKey::new(&signature::ED25519, &public_key[0..32]);
@@ -73,31 +125,79 @@ pub fn verify_u8(t: &Transaction, public_key: &[u8;32], signature1: &[u8;32], si
         sig[i+32] = signature2[i];
     }
     
-    peer_public_key.verify(bincode::serialize(&t).unwrap().as_ref(), &sig[0..64]).is_ok()
+    peer_public_key.verify(&t.canonical_bytes(), &sig[0..64]).is_ok()
     // unimplemented!()
 }
 
- pub fn bonus_tx (key: &Ed25519KeyPair) -> SignedTransaction {
+ /// Coinbase of the block at `height` paying `reward`, the block reward plus fees, to the owner of `key`.
+ /// The height goes into the input index so that every coinbase has its own hash.
+ pub fn bonus_tx (key: &Ed25519KeyPair, reward: u32, height: u32) -> SignedTransaction {
      let public_key = key.public_key();
      let rec_pk = pk_to_u8(public_key);
      let add = pk_to_h160(&rec_pk);    //address
 
-     let input = TxInput{prev_tx:[0;32].into(), index: 0,};
-     let output = TxOutput{value: 200000000, address: add, }; //receiver_pk : rec_pk,// mining fee = 2BTC, for miner
-
-     let tx = Transaction{input: input, output: output,};
+     let input = TxInput{prev_tx:[0;32].into(), index: height, unlock: Script::default(), sequence: SEQUENCE_FINAL,};
+     let output = TxOutput{value: reward, lock: Script::p2pkh(&add), }; //receiver_pk : rec_pk,// mining fee, for miner
 
-     let signature = sign(&tx, key);
-     let (signature1, signature2) = sig_to_2_u8(&signature);
+     let tx = Transaction{inputs: vec![input], outputs: vec![output], lock_time: 0,};
 
-     SignedTransaction{
-         tx: tx,
-         pk: pk_to_u8(&public_key),
-         signature1:signature1,
-         signature2:signature2,
-     }
+     sign_tx(tx, &[key])
  }
 
+/// Whether this is a miner bonus transaction, which has no real input
+pub fn is_coinbase(t: &SignedTransaction) -> bool {
+    t.tx.inputs.len() == 1 && t.tx.inputs[0].prev_tx == [0;32].into()
+}
+
+/// Whether `t` may go into a block at `height` whose parent has median time past `time`.
+/// `lock_time` is the earliest such height, or time if it is at least `LOCKTIME_THRESHOLD`, and is
+/// ignored if every input has sequence `SEQUENCE_FINAL`.
+pub fn is_final(t: &Transaction, height: u32, time: i64) -> bool {
+    if t.lock_time == 0 || t.inputs.iter().all(|input| input.sequence == SEQUENCE_FINAL) {
+        return true;
+    }
+    if t.lock_time < LOCKTIME_THRESHOLD {
+        height as u64 >= t.lock_time
+    } else {
+        time >= 0 && time as u64 >= t.lock_time
+    }
+}
+
+/// Whether `t` opts in to be replaced in the mempool by a spend of the same outputs paying more
+pub fn signals_rbf(t: &Transaction) -> bool {
+    t.inputs.iter().any(|input| input.sequence <= SEQUENCE_RBF_MAX)
+}
+
+/// Whether `input` is still locked by its sequence at `height` and median time past `time`.
+/// Unless `SEQUENCE_DISABLE_FLAG` is set, the low 16 bits of the sequence are the number of blocks,
+/// or seconds with `SEQUENCE_TYPE_FLAG`, that must pass after the block confirming `prev_entry`.
+pub fn sequence_locked(input: &TxInput, prev_entry: &UtxoEntry, height: u32, time: i64) -> bool {
+    if input.sequence & SEQUENCE_DISABLE_FLAG != 0 {
+        return false;
+    }
+    let value = input.sequence & SEQUENCE_MASK;
+    if input.sequence & SEQUENCE_TYPE_FLAG != 0 {
+        time < prev_entry.time + value as i64 * 1000
+    } else {
+        height < prev_entry.height + value
+    }
+}
+
+/// Total value of the outputs of a transaction
+pub fn output_sum(tx: &Transaction) -> u64 {
+    tx.outputs.iter().map(|output| output.value as u64).sum()
+}
+
+/// Fee paid by `t`: the value of the outputs it spends from `state` minus the value it creates.
+/// `None` if an input is not in `state` or the outputs are worth more than the inputs.
+pub fn tx_fee(t: &SignedTransaction, state: &UtxoSet) -> Option<u64> {
+    let mut input_sum: u64 = 0;
+    for input in t.tx.inputs.iter() {
+        input_sum += state.get(&OutPoint::from(input))?.value as u64;
+    }
+    input_sum.checked_sub(output_sum(&t.tx))
+}
+
 pub fn pk_to_h160(public_key: &[u8;32]) -> H160 {
     let pk_h256:H256 = ring::digest::digest(&ring::digest::SHA256, public_key).into();
     convert_to_h160(pk_h256)
@@ -126,54 +226,109 @@ pub fn sig_to_2_u8(signature: &Signature) -> ([u8;32],[u8;32]) {
 
 
 
-pub fn check_tx(t: &SignedTransaction, state: &HashMap<H256,TxOutput>) -> bool {
-    // 验证tx的合理性
-    // 1. 检查sig是否是pk签的
-    let signature1 = t.signature1;
-    let signature2 = t.signature2;
-    let pk = t.clone().pk;
-    let tx = t.clone().tx;
-    // check sig
-    let sig_check = verify_u8(&tx, &pk, &signature1, &signature2);
-    // check input 是否可花
-    let input_check:bool = state.contains_key(&(tx.input.prev_tx)) || tx.input.prev_tx == [0;32].into();
-    let mut owner_check = false;
-    let mut value_check = false;
-    // check 所有权与金额
-    if input_check == true {
-        let input_value = state.get(&(tx.input.prev_tx)).unwrap(); 
-        let add_h160 = pk_to_h160(&pk);
-        owner_check = add_h160==input_value.address;
-        value_check = input_value.value >= tx.output.value;
+pub const MAX_TX_SIGNATURES: usize = 20;
+
+/// Reasons `check_tx` rejects a transaction
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum TxValidationError {
+    BadSignature,
+    NoSignatures,
+    TooManySignatures,
+    NoInputs,
+    NoOutputs,
+    DuplicateInput(OutPoint),
+    MissingInput(OutPoint),     // 不存在或已经被花
+    ScriptFailed { outpoint: OutPoint, error: ScriptError },    // 解锁脚本打不开锁定脚本
+    ImmatureCoinbase { outpoint: OutPoint, spendable_at: u32 },
+    NotFinal { lock_time: u64 },    // 还没到lock_time
+    SequenceLocked(OutPoint),       // input的相对时间锁还没到
+    ValueOverflow,
+    Overspend { input: u64, output: u64 },
+}
+
+/// Every signature a transaction carries has to be valid, even if no script asks for it.
+/// Returns the signers' public keys for the scripts to check against.
+fn check_signatures(t: &SignedTransaction) -> Result<HashSet<[u8; 32]>, TxValidationError> {
+    if t.signatures.is_empty() {
+        return Err(TxValidationError::NoSignatures);
     }
-    
-    input_check && owner_check && sig_check && value_check
+    if t.signatures.len() > MAX_TX_SIGNATURES {
+        return Err(TxValidationError::TooManySignatures);
+    }
+    let mut signers = HashSet::new();
+    for s in t.signatures.iter() {
+        if !verify_u8(&t.tx, &s.pk, &s.signature1, &s.signature2) {
+            return Err(TxValidationError::BadSignature);
+        }
+        signers.insert(s.pk);
+    }
+    Ok(signers)
 }
 
-pub fn check_tx_missprev(t: &SignedTransaction, state: &HashMap<H256,TxOutput>) -> bool {
+/// Check that `t` may be included in a block at `height` whose parent has median time past `time`:
+/// correctly signed, spending unspent outputs whose locking scripts its unlocking scripts satisfy,
+/// including coinbase outputs only after `maturity` blocks, past its absolute and relative
+/// timelocks, and not overspending
+pub fn check_tx(t: &SignedTransaction, state: &UtxoSet, height: u32, time: i64, maturity: u32) -> Result<(), TxValidationError> {
     // 验证tx的合理性
-    // 1. 检查sig是否是pk签的
+    // 1. 检查每个sig是否是对应pk签的
+    let signers = check_signatures(t)?;
+    if t.tx.inputs.is_empty() {
+        return Err(TxValidationError::NoInputs);
+    }
+    if t.tx.outputs.is_empty() {
+        return Err(TxValidationError::NoOutputs);
+    }
+    if !is_final(&t.tx, height, time) {
+        return Err(TxValidationError::NotFinal { lock_time: t.tx.lock_time });
+    }
+    // 2. 每个input都可花、脚本通过、且不重复
+    let ctx = ScriptContext { signers: signers, height: height, time: time };
+    let mut seen = HashSet::new();
+    let mut input_sum: u64 = 0;
+    for input in t.tx.inputs.iter() {
+        let outpoint = OutPoint::from(input);
+        if !seen.insert(outpoint) {
+            return Err(TxValidationError::DuplicateInput(outpoint));
+        }
+        let prev_entry = state.entry(&outpoint).ok_or(TxValidationError::MissingInput(outpoint))?;
+        verify_script(&input.unlock, &prev_entry.output.lock, &ctx)
+            .map_err(|e| TxValidationError::ScriptFailed { outpoint: outpoint, error: e })?;
+        // coinbase的output要等足够多确认才能花
+        let spendable_at = prev_entry.height.saturating_add(maturity);
+        if prev_entry.is_coinbase && height < spendable_at {
+            return Err(TxValidationError::ImmatureCoinbase { outpoint: outpoint, spendable_at: spendable_at });
+        }
+        if sequence_locked(input, prev_entry, height, time) {
+            return Err(TxValidationError::SequenceLocked(outpoint));
+        }
+        input_sum = input_sum.checked_add(prev_entry.output.value as u64).ok_or(TxValidationError::ValueOverflow)?;
+    }
+    // 3. 输出总和不超过输入总和
+    let output_sum = t.tx.outputs.iter()
+        .try_fold(0u64, |sum, output| sum.checked_add(output.value as u64))
+        .ok_or(TxValidationError::ValueOverflow)?;
+    if output_sum > input_sum {
+        return Err(TxValidationError::Overspend { input: input_sum, output: output_sum });
+    }
+    Ok(())
+}
+
+/// Check what can be checked of a transaction whose parents may not have arrived yet:
+/// the signature, and the scripts of every spent output that is already in `state`
+pub fn check_tx_missprev(t: &SignedTransaction, state: &UtxoSet, height: u32, time: i64) -> Result<(), TxValidationError> {
     // 验证tx的合理性
-    // 1. 检查sig是否是pk签的
-    let signature1 = t.signature1;
-    let signature2 = t.signature2;
-    let pk = t.clone().pk;
-    let tx = t.clone().tx;
-    // check sig
-    let sig_check = verify_u8(&tx, &pk, &signature1, &signature2);
-    // check input 是否可花
-    let input_check:bool = state.contains_key(&(tx.input.prev_tx)) || tx.input.prev_tx == [0;32].into();
-    let mut owner_check = false;
-    let mut value_check = false;
-    // check 所有权与金额
-    if input_check == true {
-        let input_value = state.get(&(tx.input.prev_tx)).unwrap(); 
-        let add_h160 = pk_to_h160(&pk);
-        owner_check = add_h160==input_value.address;
-        value_check = input_value.value >= tx.output.value;
+    // 1. 检查每个sig是否是对应pk签的
+    let signers = check_signatures(t)?;
+    let ctx = ScriptContext { signers: signers, height: height, time: time };
+    for input in t.tx.inputs.iter() {
+        let outpoint = OutPoint::from(input);
+        if let Some(prev_output) = state.get(&outpoint) {
+            verify_script(&input.unlock, &prev_output.lock, &ctx)
+                .map_err(|e| TxValidationError::ScriptFailed { outpoint: outpoint, error: e })?;
+        }
     }
-    
-    !input_check && owner_check && sig_check && value_check
+    Ok(())
 }
 
 pub fn generate_random_transaction() -> Transaction {
@@ -184,34 +339,32 @@ pub fn generate_random_transaction() -> Transaction {
     let Value : u32 = rng.gen();
     let rec_pk : [u8;32] = rng.gen();
     let add = pk_to_h160(&rec_pk);
-    let mut input = TxInput{prev_tx :bf_tx.into(), index : bf_idx};
-    let mut output = TxOutput{value:Value, address: add.into(), };//receiver_pk : rec_pk
+    let mut input = TxInput{prev_tx :bf_tx.into(), index : bf_idx, unlock: Script::default(), sequence: SEQUENCE_FINAL};
+    let mut output = TxOutput{value:Value, lock: Script::p2pkh(&add), };//receiver_pk : rec_pk
 
     Transaction{
-        input : input,
-        output : output,
+        inputs : vec![input],
+        outputs : vec![output],
+        lock_time: 0,
     }
 }
 
 pub fn generate_confirmed_signedtransaction(
-    state: &HashMap<H256, TxOutput>, 
+    state: &UtxoSet, 
     keypair: &HashMap<H160, Ed25519KeyPair>) -> SignedTransaction {
     // generate transaction
     let mut rng = rand::thread_rng();
     let rec_key_num = rng.gen_range(0,keypair.len());
-    let mut hash:H256 = [0;32].into(); 
+    let mut hash = OutPoint::default(); 
     let mut num = 0;
     let mut value:u32 = 0;
     let mut add:H160 = [0;20].into();
 
     //选state中一个作为输入
-    let mut self_state = state.clone();
-    for j in state.keys() {
-        let address_of_state = state.get(j).unwrap().address;
-        if keypair.contains_key(&address_of_state) {
-            continue;
-        } else {
-            self_state.remove(j);
+    let mut self_state = HashMap::new();
+    for (j, entry) in state.iter() {
+        if entry.output.lock.p2pkh_address().map_or(false, |address| keypair.contains_key(&address)) {
+            self_state.insert(*j, entry.output.clone());
         }
     }
     let rand_hash = rng.gen_range(1,self_state.len()); // miner's state
@@ -220,7 +373,7 @@ pub fn generate_confirmed_signedtransaction(
             hash = i.clone();
             value = state.get(i).unwrap().value;
             //pk = state.get(i).unwrap().receiver_pk;
-            add = state.get(i).unwrap().address;
+            add = state.get(i).unwrap().lock.p2pkh_address().unwrap();
         }
         num += 1;
     }
@@ -238,46 +391,55 @@ pub fn generate_confirmed_signedtransaction(
         index += 1;
     }
 
-    
-    let input = TxInput{prev_tx :hash, index : 0};
-    let output = TxOutput{value:value, address: rec_add,}; // receiver_pk: rec_pk
+
+    // 同一地址的另一个state一起花掉，合并零钱
+    let mut inputs = vec![TxInput{prev_tx :hash.txid, index : hash.index, unlock: Script::p2pkh_unlock(&pk), sequence: SEQUENCE_FINAL}];
+    for i in self_state.keys() {
+        if *i == hash || state.get(i).unwrap().lock.p2pkh_address() != Some(add) {
+            continue;
+        }
+        if let Some(total) = value.checked_add(state.get(i).unwrap().value) {
+            value = total;
+            inputs.push(TxInput{prev_tx :i.txid, index : i.index, unlock: Script::p2pkh_unlock(&pk), sequence: SEQUENCE_FINAL});
+            break;
+        }
+    }
+    // 付一部分给接收方，剩下的找零给自己
+    let pay = rng.gen_range(0, value as u64 + 1) as u32;
+    let mut outputs = vec![TxOutput{value:pay, lock: Script::p2pkh(&rec_add),}]; // receiver_pk: rec_pk
+    if value > pay {
+        outputs.push(TxOutput{value:value - pay, lock: Script::p2pkh(&add),});
+    }
 
     let tx = Transaction{
-        input : input,
-        output : output,
+        inputs : inputs,
+        outputs : outputs,
+        lock_time: 0,
     };
 
-    let signature = sign(&tx,key);
-    let (sig1,sig2) = sig_to_2_u8(&signature);
-
-    SignedTransaction{
-        tx:tx,
-        pk:pk,
-        signature1: sig1,
-        signature2: sig2,
-    }
+    sign_tx(tx, &[key])
 }
 
 pub fn generate_advers_signedtransaction(
-    state: &HashMap<H256, TxOutput>, 
+    state: &UtxoSet, 
     keypair: &HashMap<H160, Ed25519KeyPair>) -> SignedTransaction {
     // generate transaction
     let mut rng = rand::thread_rng();
     let rand_hash = rng.gen_range(0,state.len()) - 1;
     let rec_key_num = rng.gen_range(0,keypair.len() - 1);
-    let mut hash:H256 = [0;32].into(); 
+    let mut hash = OutPoint::default(); 
     let mut num = 0;
     let mut value:u32 = 0;
 
     let mut add:H160 = [0;20].into();
     
     //选state中一个作为输入
-    for i in state.keys() {
+    for i in state.outpoints() {
         if num == rand_hash {
             hash = i.clone();
             value = state.get(i).unwrap().value;
             //pk = state.get(i).unwrap().receiver_pk;
-            add = state.get(i).unwrap().address;
+            add = state.get(i).unwrap().lock.p2pkh_address().unwrap_or_default();
         }
         num += 1;
     }
@@ -296,23 +458,16 @@ pub fn generate_advers_signedtransaction(
     }
 
     
-    let input = TxInput{prev_tx :hash, index : 0};
-    let output = TxOutput{value:value, address: rec_add,}; // receiver_pk: rec_pk
+    let input = TxInput{prev_tx :hash.txid, index : hash.index, unlock: Script::p2pkh_unlock(&pk), sequence: SEQUENCE_FINAL};
+    let output = TxOutput{value:value, lock: Script::p2pkh(&rec_add),}; // receiver_pk: rec_pk
 
     let tx = Transaction{
-        input : input,
-        output : output,
+        inputs : vec![input],
+        outputs : vec![output],
+        lock_time: 0,
     };
 
-    let signature = sign(&tx,&key);
-    let (sig1,sig2) = sig_to_2_u8(&signature);
-
-    SignedTransaction{
-        tx:tx,
-        pk:pk,
-        signature1: sig1,
-        signature2: sig2,
-    }
+    sign_tx(tx, &[&key])
 }
 
 
@@ -330,20 +485,13 @@ pub fn generate_state(keypair: &HashMap<H160, Ed25519KeyPair>) -> TxOutput {
     }
     let value:u32 = rng.gen();
     let add = pk_to_h160(&pk);
-    TxOutput{value:value, address: add, } //receiver_pk:pk,
+    TxOutput{value:value, lock: Script::p2pkh(&add), } //receiver_pk:pk,
 }
 
 pub fn generate_signed_txs() -> SignedTransaction {
     let t = generate_random_transaction();
     let key = key_pair::random();
-    let signature = sign(&t, &key);
-    let (sig1,sig2) = sig_to_2_u8(&signature);
-    SignedTransaction {
-        tx: t,
-        signature1: sig1,
-        signature2: sig2,
-        pk: pk_to_u8(key.public_key()),
-    }
+    sign_tx(t, &[&key])
 
 }
 
@@ -360,6 +508,25 @@ mod tests {
         let signature = sign(&t, &key);
         assert!(verify(&t, &(key.public_key()), &signature));
     }
+
+    #[test]
+    fn txid_excludes_signatures() {
+        let t = generate_random_transaction();
+        let key = key_pair::random();
+        let signed = sign_tx(t.clone(), &[&key]);
+        let cosigned = sign_tx(t.clone(), &[&key, &key_pair::random()]);
+        assert_eq!(signed.txid(), t.hash());
+        assert_eq!(signed.txid(), cosigned.txid());
+        assert_ne!(signed.wtxid(), cosigned.wtxid());
+
+        // merkle root也要随签名变
+        let mut content = crate::block::Content::default();
+        content.transactions.push(signed.txid());
+        content.transaction_detail.insert(signed.txid(), signed);
+        let root = content.merkle_root();
+        content.transaction_detail.insert(cosigned.txid(), cosigned);
+        assert_ne!(content.merkle_root(), root);
+    }
 }
 
 #[cfg(any(test, test_utilities))]
@@ -371,26 +538,24 @@ mod tests_Liz {
     fn sign_verify() {
         let tx = generate_signed_txs();
         let t = tx.clone().tx;
-        let key = tx.clone().pk;
+        let key = tx.clone().signatures[0].pk;
         let mut pk_u8 = [0;32];
         let mut sig1  = [0;32];
         let mut sig2  = [0;32];
         for i in 0..32 {
             pk_u8[i] = key.as_ref()[i];
-            sig1[i] = tx.signature1[i];
-            sig2[i] = tx.signature2[i];
+            sig1[i] = tx.signatures[0].signature1[i];
+            sig2[i] = tx.signatures[0].signature2[i];
             
         }
 
         let txu8 = SignedTransaction{
             tx:t.clone() ,
-            pk: pk_u8,
-            signature1: sig1,
-            signature2: sig2,
+            signatures: vec![TxSignature{pk: pk_u8, signature1: sig1, signature2: sig2}],
         };
         //println!{"{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}", key.as_ref(), pk_u8,  tx.signature1,sig1, tx.signature2 ,sig2};
 
-        let mut out = txu8.tx.output;
+        let mut out = txu8.tx.outputs[0].clone();
         out.value = out.value;
 
         //println!{"-----------------------------{:?}",verify_u8(&t, &pk_u8,&sig1,&sig2 )};
@@ -412,15 +577,15 @@ mod tests_Liz {
             keypair.insert(add, key);
         }
 
-        let mut state:HashMap<H256,TxOutput> = HashMap::new();
+        let mut state = UtxoSet::new();
         for i in 0..4 {
             let hash:[u8;32] = rng.gen();
             let hash256: H256 = hash.into();
-            state.insert(hash256, generate_state(&keypair));
+            state.add(OutPoint::new(hash256, 0), generate_state(&keypair));
         }
         let mut tx  = generate_confirmed_signedtransaction(&state, &keypair);
         //tx.pk = [1;32];
-        let check = check_tx(&tx, &state);
+        let check = check_tx(&tx, &state, 1, 0, 0).is_ok();
 
         loop {
             let rand = rng.gen_range(0,state.len());
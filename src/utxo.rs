@@ -0,0 +1,164 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::collections::hash_map;
+
+use crate::crypto::hash::H256;
+use crate::transaction::{SignedTransaction, TxInput, TxOutput, is_coinbase};
+
+/// One output of one transaction
+#[derive(Hash, Eq, PartialEq, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub txid: H256,
+    pub index: u32,
+}
+
+impl OutPoint {
+    pub fn new(txid: H256, index: u32) -> Self {
+        OutPoint {
+            txid: txid,
+            index: index,
+        }
+    }
+}
+
+impl From<&TxInput> for OutPoint {
+    fn from(input: &TxInput) -> OutPoint {
+        OutPoint::new(input.prev_tx, input.index)
+    }
+}
+
+/// An unspent output together with where it was created
+#[derive(Eq, PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UtxoEntry {
+    pub output: TxOutput,
+    pub height: u32,        // 所在块高度，不在块里的state为0
+    pub time: i64,          // 所在块的父块的median time past，用于相对时间锁
+    pub is_coinbase: bool,
+}
+
+/// Unspent transaction outputs, keyed by the outpoint that creates them
+#[derive(Eq, PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UtxoSet {
+    outputs: HashMap<OutPoint, UtxoEntry>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        UtxoSet {
+            outputs: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.outputs.contains_key(outpoint)
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&TxOutput> {
+        self.outputs.get(outpoint).map(|entry| &entry.output)
+    }
+
+    pub fn entry(&self, outpoint: &OutPoint) -> Option<&UtxoEntry> {
+        self.outputs.get(outpoint)
+    }
+
+    /// Add an output that is not from a coinbase, such as a synchronized state
+    pub fn add(&mut self, outpoint: OutPoint, output: TxOutput) {
+        self.add_entry(outpoint, UtxoEntry { output: output, height: 0, time: 0, is_coinbase: false });
+    }
+
+    pub fn add_entry(&mut self, outpoint: OutPoint, entry: UtxoEntry) {
+        self.outputs.insert(outpoint, entry);
+    }
+
+    /// Remove an output from the set, returning it if it was unspent
+    pub fn spend(&mut self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+        self.outputs.remove(outpoint)
+    }
+
+    pub fn outpoints(&self) -> hash_map::Keys<'_, OutPoint, UtxoEntry> {
+        self.outputs.keys()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, OutPoint, UtxoEntry> {
+        self.outputs.iter()
+    }
+
+    /// Sum of the values of all unspent outputs
+    pub fn total_value(&self) -> u64 {
+        self.outputs.values().map(|entry| entry.output.value as u64).sum()
+    }
+
+    /// Spend the inputs of `t` and add its outputs as created at `height`, in a block whose parent has
+    /// median time past `time`.
+    /// Returns the entries that were spent, which `undo_tx` needs to revert the transaction.
+    pub fn apply_tx(&mut self, t: &SignedTransaction, height: u32, time: i64) -> HashMap<OutPoint, UtxoEntry> {
+        let mut spent = HashMap::new();
+        let coinbase = is_coinbase(t);
+        if !coinbase {
+            for input in t.tx.inputs.iter() {
+                let outpoint = OutPoint::from(input);
+                if let Some(prev_entry) = self.spend(&outpoint) {
+                    spent.insert(outpoint, prev_entry);
+                }
+            }
+        }
+        let txid = t.txid();
+        for (index, output) in t.tx.outputs.iter().enumerate() {
+            let entry = UtxoEntry { output: output.clone(), height: height, time: time, is_coinbase: coinbase };
+            self.add_entry(OutPoint::new(txid, index as u32), entry);
+        }
+        spent
+    }
+
+    /// Remove the outputs of `t` and give back the ones it spent
+    pub fn undo_tx(&mut self, t: &SignedTransaction, spent: &HashMap<OutPoint, UtxoEntry>) {
+        let txid = t.txid();
+        for index in 0..t.tx.outputs.len() {
+            self.spend(&OutPoint::new(txid, index as u32));
+        }
+        for input in t.tx.inputs.iter() {
+            let outpoint = OutPoint::from(input);
+            if let Some(prev_entry) = spent.get(&outpoint) {
+                self.add_entry(outpoint, prev_entry.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::generate_signed_txs;
+    use crate::script::Script;
+
+    #[test]
+    fn apply_and_undo() {
+        let mut utxo = UtxoSet::new();
+        let mut t = generate_signed_txs();
+        t.tx.outputs.push(TxOutput{value: 1, lock: Script::p2pkh(&[1;20].into())});
+        let prev = OutPoint::from(&t.tx.inputs[0]);
+        utxo.add(prev, TxOutput{value: 5, lock: Script::p2pkh(&[2;20].into())});
+        utxo.add(OutPoint::new(prev.txid, 1), TxOutput{value: 6, lock: Script::p2pkh(&[2;20].into())});
+        let before = utxo.clone();
+
+        let spent = utxo.apply_tx(&t, 3, 1000);
+        assert_eq!(spent.len(), 1);
+        assert!(!utxo.contains(&prev));
+        assert!(utxo.contains(&OutPoint::new(prev.txid, 1)));
+        assert_eq!(utxo.get(&OutPoint::new(t.txid(), 1)).unwrap().value, 1);
+        assert_eq!(utxo.entry(&OutPoint::new(t.txid(), 1)).unwrap().height, 3);
+        assert_eq!(utxo.len(), 3);
+        assert_eq!(utxo.total_value(), 6 + t.tx.outputs[0].value as u64 + 1);
+
+        utxo.undo_tx(&t, &spent);
+        assert_eq!(utxo, before);
+    }
+}